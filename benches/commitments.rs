@@ -0,0 +1,72 @@
+use std::ops::Mul;
+
+use ark_ec::CurveGroup;
+use ark_ff::UniformRand;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mina_curves::pasta::Fq;
+use oram_smst::{
+    node_position::Height,
+    nodes::partial::PartialNode,
+    pedersen::Pedersen,
+    record::random_records,
+    salt::Salt,
+    secret::random_secret,
+    tree::{TreeBuilder, TreeParams},
+};
+
+/// one commitment per leaf with two variable base scalar multiplications, the path before batching
+fn commit_naive(pedersen: &Pedersen, openings: &[(Fq, Fq)]) {
+    for (value, blinding) in openings {
+        let commitment = pedersen.base.mul(value) + pedersen.base_blinding.mul(blinding);
+        std::hint::black_box(commitment.into_affine());
+    }
+}
+
+fn commitments(c: &mut Criterion) {
+    let mut rng = ark_std::rand::thread_rng();
+    let pedersen = Pedersen::get();
+    let mut group = c.benchmark_group("commitments");
+    for size in [1_000, 10_000] {
+        let openings: Vec<(Fq, Fq)> = (0..size)
+            .map(|_| (Fq::from(u64::rand(&mut rng)), Fq::rand(&mut rng)))
+            .collect();
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("naive", size), &openings, |b, openings| {
+            b.iter(|| commit_naive(pedersen, openings))
+        });
+        group.bench_with_input(BenchmarkId::new("batch", size), &openings, |b, openings| {
+            b.iter(|| pedersen.commit_batch(openings))
+        });
+    }
+    group.finish();
+}
+
+fn tree_build(c: &mut Criterion) {
+    let records = random_records::<3>(10_000);
+    let tree_params = TreeParams {
+        master_secret: random_secret(),
+        salt_s: Salt::generate_random(),
+        salt_b: Salt::generate_random(),
+    };
+    let mut group = c.benchmark_group("tree_build");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(records.len() as u64));
+    group.bench_function("single_threaded", |b| {
+        b.iter(|| {
+            let mut tree_builder: TreeBuilder<PartialNode, 3> =
+                TreeBuilder::new(records.clone(), Height::new(20), tree_params.clone());
+            tree_builder.build_single_threaded(None).unwrap()
+        })
+    });
+    group.bench_function("multi_threaded", |b| {
+        b.iter(|| {
+            let mut tree_builder: TreeBuilder<PartialNode, 3> =
+                TreeBuilder::new(records.clone(), Height::new(20), tree_params.clone());
+            tree_builder.build_multi_threaded(None).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, commitments, tree_build);
+criterion_main!(benches);
@@ -0,0 +1,208 @@
+use std::{error::Error, net::SocketAddr, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use oram_smst::{
+    config::Config,
+    db::{collect_records, csv::Csv, Storage},
+    keyfile,
+    node_position::{Height, NodePosition},
+    nodes::{node::Node, partial::PartialNode},
+    proofs::MerkleWitness,
+    smt::NodeContent,
+    snapshot,
+    tree::{TreeBuilder, TreeParams, SMT},
+};
+use prost::Message;
+use sha2::Digest;
+
+/// The witnesses do not depend on the number of assets, the type only needs one
+const WITNESS_N_CURR: usize = 3;
+
+#[derive(Parser)]
+#[command(
+    name = "oram_smst",
+    about = "Build, prove and verify ORAM based sparse merkle sum trees"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// builds the tree from a csv file and writes a snapshot
+    Build {
+        #[arg(long)]
+        input: PathBuf,
+        #[arg(long)]
+        out: PathBuf,
+        /// height of the tree, derived from the number of records when omitted
+        #[arg(long)]
+        height: Option<u8>,
+        /// number of assets in every record
+        #[arg(long, default_value_t = 3)]
+        n_assets: usize,
+        /// epoch of the placement seed, the same epoch and records always give the same root
+        #[arg(long, default_value_t = 0)]
+        epoch: u64,
+        /// keyfile with the tree params
+        #[arg(long, required_unless_present = "dev")]
+        keyfile: Option<PathBuf>,
+        /// uses the demo params instead of a keyfile
+        #[arg(long, conflicts_with = "keyfile")]
+        dev: bool,
+    },
+    /// writes the merkle witness of a user from a snapshot, with the range proofs of its nodes
+    Prove {
+        /// the plain email of the user
+        #[arg(long)]
+        user: String,
+        #[arg(long, default_value = "tree.snap")]
+        tree: PathBuf,
+        /// keyfile the tree was built with
+        #[arg(long, required_unless_present = "dev")]
+        keyfile: Option<PathBuf>,
+        /// uses the demo params instead of a keyfile
+        #[arg(long, conflicts_with = "keyfile")]
+        dev: bool,
+        /// defaults to proofs/<hashed email>.json
+        #[arg(long)]
+        out: Option<String>,
+    },
+    /// verifies a witness written by `prove` and its range proofs against a published root
+    Verify {
+        #[arg(long)]
+        proof: String,
+        /// the root as printed by `build`
+        #[arg(long, required_unless_present = "config")]
+        root: Option<String>,
+        /// verifies against the latest root published in the data source of this config
+        #[arg(long, conflicts_with = "root")]
+        config: Option<PathBuf>,
+    },
+    /// generates fresh tree params into an encrypted keyfile
+    /// the password is read from `ORAM_SMST_KEYFILE_PASSWORD`
+    Keygen {
+        #[arg(long, required_unless_present = "config")]
+        out: Option<PathBuf>,
+        /// stores the encrypted params in the data source of this config instead of a file
+        #[arg(long, conflicts_with = "out")]
+        config: Option<PathBuf>,
+    },
+    /// starts the grpc server
+    Serve {
+        /// TOML config file, `ORAM_SMST_*` environment variables override it
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// overrides the listen address of the config
+        #[arg(long)]
+        addr: Option<SocketAddr>,
+        /// allows the demo secrets
+        #[arg(long)]
+        dev: bool,
+    },
+}
+
+pub async fn build<const N_CURR: usize>(
+    input: PathBuf,
+    out: PathBuf,
+    height: Option<u8>,
+    epoch: u64,
+    keyfile: Option<PathBuf>,
+    dev: bool,
+) -> Result<(), Box<dyn Error>> {
+    let csv_db = csv_db(input);
+    let tree_params = tree_params(keyfile, dev)?;
+    let records = collect_records::<N_CURR>(&csv_db).await?;
+    // the snapshot keeps the openings of the nodes so `prove` can attach range proofs
+    let mut tree_builder: TreeBuilder<Node, N_CURR> = match height {
+        Some(height) => TreeBuilder::new(records, Height::new(height), tree_params.clone()),
+        None => TreeBuilder::from_records(records, tree_params.clone()),
+    }
+    .with_epoch(epoch);
+    let (tree, record_map) = tree_builder.build_single_threaded(None)?;
+    snapshot::save(&tree, &record_map, &tree_params, &out)?;
+    println!("{}", encode_root(&tree.root.to_partial()));
+    Ok(())
+}
+
+pub fn prove(
+    user: String,
+    tree: PathBuf,
+    keyfile: Option<PathBuf>,
+    dev: bool,
+    out: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let tree_params = tree_params(keyfile, dev)?;
+    let (tree, record_map): (SMT<Node>, _) = snapshot::load(&tree, &tree_params)?;
+    let hashed_email = hex::encode(sha2::Sha256::digest(user));
+    let padding_fn = |pos: &NodePosition| tree_params.padding_node_content(pos);
+    let mut witness: MerkleWitness<Node, WITNESS_N_CURR> =
+        MerkleWitness::generate_witness(hashed_email, &tree, &record_map, &padding_fn)?;
+    witness.attach_range_proofs()?;
+    witness.to_partial().save(out.as_deref())?;
+    Ok(())
+}
+
+pub async fn verify(
+    proof: String,
+    root: Option<String>,
+    config: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let root = match (root, config) {
+        (_, Some(config)) => Config::load(Some(&config))?
+            .db()
+            .await?
+            .latest_root()
+            .await?
+            .ok_or("no root was published in the data source")?,
+        (Some(root), None) => decode_root(&root)?,
+        (None, None) => return Err("either --root or --config is required".into()),
+    };
+    let witness: MerkleWitness<PartialNode, WITNESS_N_CURR> = MerkleWitness::load(&proof)?;
+    witness.verify(&root)?;
+    // a witness without range proofs is refused, its commitments could hide negative liabilities
+    witness.verify_range_proofs()?;
+    println!("proof is valid");
+    Ok(())
+}
+
+pub async fn keygen(out: Option<PathBuf>, config: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let password = keyfile::password_from_env()?;
+    let tree_params = keyfile::generate_tree_params();
+    match (out, config) {
+        (_, Some(config)) => {
+            let db = Config::load(Some(&config))?.db().await?;
+            db.set_tree_params(&tree_params, &password).await?;
+        }
+        (Some(out), None) => keyfile::save(&tree_params, &password, out)?,
+        (None, None) => return Err("either --out or --config is required".into()),
+    }
+    Ok(())
+}
+
+/// the params of the keyfile, the demo params are only used when asked for with `--dev`
+fn tree_params(keyfile: Option<PathBuf>, dev: bool) -> Result<TreeParams, Box<dyn Error>> {
+    match (keyfile, dev) {
+        (Some(path), _) => Ok(keyfile::load(path, &keyfile::password_from_env()?)?),
+        (None, true) => Ok(TreeParams::demo()),
+        (None, false) => Err("either --keyfile or --dev is required".into()),
+    }
+}
+
+fn csv_db(input: PathBuf) -> Csv {
+    Csv {
+        file: input.to_string_lossy().into_owned(),
+    }
+}
+
+/// the root is published as the hex of its encoded `NodeContent`
+fn encode_root(root: &PartialNode) -> String {
+    let content: NodeContent = root.clone().into();
+    hex::encode(content.encode_to_vec())
+}
+
+fn decode_root(root: &str) -> Result<PartialNode, Box<dyn Error>> {
+    let content = NodeContent::decode(hex::decode(root)?.as_slice())?;
+    Ok(PartialNode::try_from(content)?)
+}
@@ -0,0 +1,372 @@
+use std::{error::Error, fs, net::SocketAddr, path::Path, str::FromStr, sync::Arc};
+
+use serde::Deserialize;
+use zeroize::Zeroizing;
+
+#[cfg(feature = "postgres")]
+use crate::db::postgres::{Postgres, PostgresConfig};
+#[cfg(feature = "rocksdb")]
+use crate::db::rocksdb::RocksDb;
+#[cfg(feature = "sqlite")]
+use crate::db::sqlite::Sqlite;
+use crate::{
+    db::{csv::Csv, Storage},
+    error::{ErrorKind, Result},
+    keyfile,
+    tree::TreeParams,
+};
+
+/// Prefix of the environment variables overriding the config file
+pub const ENV_PREFIX: &str = "ORAM_SMST_";
+
+/// The largest `n_assets`, the binary is only instantiated for `1..=MAX_ASSETS` assets
+pub const MAX_ASSETS: usize = 8;
+
+/// Server configuration read from a TOML file, every field can be overridden by
+/// an `ORAM_SMST_<FIELD>` environment variable
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub addr: SocketAddr,
+    pub data_source: DataSource,
+    /// number of assets in every record, the `N_CURR` of the server, in `1..=MAX_ASSETS`
+    pub n_assets: usize,
+    /// the leaves are placed with the seed of this epoch so auditors can rebuild the tree
+    pub epoch: u64,
+    pub commitments: Commitments,
+    /// allows `commitments = "per_asset"`, its proofs have no range proofs so a negative
+    /// liability can hide in the per asset commitments
+    pub unsafe_allow_unproven_per_asset: bool,
+    pub secret_source: SecretSource,
+    pub log_level: String,
+    /// allows starting with the demo secrets
+    pub dev: bool,
+}
+
+/// Where the records are read from
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DataSource {
+    Csv {
+        path: String,
+    },
+    /// a RocksDB database directory, created when missing
+    #[cfg(feature = "rocksdb")]
+    #[serde(rename = "rocksdb")]
+    RocksDb {
+        path: String,
+    },
+    /// a table of the ledger, the tree params must come from a keyfile
+    #[cfg(feature = "postgres")]
+    Postgres(PostgresConfig),
+    /// a SQLite file, created when missing
+    #[cfg(feature = "sqlite")]
+    Sqlite {
+        path: String,
+    },
+}
+
+impl DataSource {
+    /// replaces the path of a file backed source
+    fn set_path(&mut self, new_path: String) -> Result<()> {
+        match self {
+            DataSource::Csv { path } => *path = new_path,
+            #[cfg(feature = "rocksdb")]
+            DataSource::RocksDb { path } => *path = new_path,
+            #[cfg(feature = "sqlite")]
+            DataSource::Sqlite { path } => *path = new_path,
+            #[cfg(feature = "postgres")]
+            DataSource::Postgres(_) => {
+                return Err(ErrorKind::Config(
+                    "DATA_PATH cannot be set for a postgres data source".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the commitments of the leaves open to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Commitments {
+    /// the total liability of the user, every commitment of a proof comes with a range proof
+    #[default]
+    Total,
+    /// every balance of the user separately, the proofs have no range proofs so it needs
+    /// `unsafe_allow_unproven_per_asset`
+    PerAsset,
+}
+
+impl FromStr for Commitments {
+    type Err = ErrorKind;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "total" => Ok(Commitments::Total),
+            "per_asset" => Ok(Commitments::PerAsset),
+            _ => Err(ErrorKind::Config(format!("unknown commitments {}", value))),
+        }
+    }
+}
+
+/// Where the tree params are read from
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SecretSource {
+    /// the tree params stored encrypted alongside the records with `oram_smst keygen --config`,
+    /// the password is read from `ORAM_SMST_KEYFILE_PASSWORD`, the csv only has the demo params
+    DataSource,
+    /// an encrypted keyfile, the password is read from `ORAM_SMST_KEYFILE_PASSWORD`
+    Keyfile { path: String },
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addr: "[::1]:50051".parse().unwrap(),
+            data_source: DataSource::Csv {
+                path: Csv::default().file,
+            },
+            n_assets: 3,
+            epoch: 0,
+            commitments: Commitments::Total,
+            unsafe_allow_unproven_per_asset: false,
+            secret_source: SecretSource::DataSource,
+            log_level: "info".to_string(),
+            dev: false,
+        }
+    }
+}
+
+impl Config {
+    /// reads the config file if given and applies the environment overrides
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let config = match path {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|err| ErrorKind::Config(format!("{}: {}", path.display(), err)))?;
+                Self::from_toml_str(&contents)?
+            }
+            None => Self::default(),
+        };
+        config
+            .with_env_overrides(|key| std::env::var(format!("{}{}", ENV_PREFIX, key)).ok())?
+            .validate()
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self> {
+        toml::from_str(contents).map_err(|err| ErrorKind::Config(err.to_string()))
+    }
+
+    fn with_env_overrides<F: Fn(&str) -> Option<String>>(mut self, var: F) -> Result<Self> {
+        if let Some(addr) = var("ADDR") {
+            self.addr = parse_var("ADDR", &addr)?;
+        }
+        if let Some(path) = var("DATA_PATH") {
+            self.data_source.set_path(path)?;
+        }
+        if let Some(n_assets) = var("N_ASSETS") {
+            self.n_assets = parse_var("N_ASSETS", &n_assets)?;
+        }
+        if let Some(epoch) = var("EPOCH") {
+            self.epoch = parse_var("EPOCH", &epoch)?;
+        }
+        if let Some(commitments) = var("COMMITMENTS") {
+            self.commitments = parse_var("COMMITMENTS", &commitments)?;
+        }
+        if let Some(allow) = var("UNSAFE_ALLOW_UNPROVEN_PER_ASSET") {
+            self.unsafe_allow_unproven_per_asset =
+                parse_var("UNSAFE_ALLOW_UNPROVEN_PER_ASSET", &allow)?;
+        }
+        if let Some(log_level) = var("LOG_LEVEL") {
+            self.log_level = log_level;
+        }
+        if let Some(path) = var("KEYFILE") {
+            self.secret_source = SecretSource::Keyfile { path };
+        }
+        if let Some(dev) = var("DEV") {
+            self.dev = parse_var("DEV", &dev)?;
+        }
+        Ok(self)
+    }
+
+    /// refuses an unsupported number of assets and the settings which would lose a guarantee of
+    /// the proofs without an explicit opt-in
+    fn validate(self) -> Result<Self> {
+        if !(1..=MAX_ASSETS).contains(&self.n_assets) {
+            return Err(ErrorKind::Config(format!(
+                "n_assets must be between 1 and {}, got {}",
+                MAX_ASSETS, self.n_assets
+            )));
+        }
+        if self.commitments == Commitments::PerAsset && !self.unsafe_allow_unproven_per_asset {
+            return Err(ErrorKind::Config(
+                "per_asset commitments have no range proofs, set unsafe_allow_unproven_per_asset \
+                 to serve them anyway"
+                    .to_string(),
+            ));
+        }
+        Ok(self)
+    }
+
+    pub fn log_level(&self) -> Result<tracing::Level> {
+        parse_var("LOG_LEVEL", &self.log_level)
+    }
+
+    pub async fn db(&self) -> Result<Arc<dyn Storage>> {
+        Ok(match &self.data_source {
+            DataSource::Csv { path } => Arc::new(Csv { file: path.clone() }),
+            #[cfg(feature = "rocksdb")]
+            DataSource::RocksDb { path } => Arc::new(RocksDb::open(path)?),
+            #[cfg(feature = "postgres")]
+            DataSource::Postgres(config) => Arc::new(Postgres::connect(config.clone()).await?),
+            #[cfg(feature = "sqlite")]
+            DataSource::Sqlite { path } => Arc::new(Sqlite::open(path)?),
+        })
+    }
+
+    /// the tree params from the secret source, the demo secrets are refused unless `dev` is set
+    pub async fn tree_params(
+        &self,
+        db: &dyn Storage,
+    ) -> std::result::Result<TreeParams, Box<dyn Error>> {
+        let tree_params = match &self.secret_source {
+            SecretSource::DataSource => db.tree_params(&self.data_source_password()?).await?,
+            SecretSource::Keyfile { path } => keyfile::load(path, &keyfile::password_from_env()?)?,
+        };
+        if tree_params.is_demo() && !self.dev {
+            return Err(ErrorKind::DemoSecrets.into());
+        }
+        Ok(tree_params)
+    }
+
+    /// the csv demo params are not encrypted so no password is needed for them
+    fn data_source_password(&self) -> Result<Zeroizing<Vec<u8>>> {
+        match self.data_source {
+            DataSource::Csv { .. } => Ok(Zeroizing::new(vec![])),
+            #[allow(unreachable_patterns)]
+            _ => keyfile::password_from_env(),
+        }
+    }
+}
+
+fn parse_var<T: FromStr>(key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| ErrorKind::Config(format!("invalid value for {}: {}", key, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Commitments, Config, DataSource};
+    use crate::error::ErrorKind;
+
+    #[tokio::test]
+    async fn reads_toml_and_env_overrides() {
+        let config = Config::from_toml_str(
+            r#"
+            addr = "127.0.0.1:6000"
+            n_assets = 5
+            log_level = "debug"
+            commitments = "per_asset"
+
+            [data_source]
+            kind = "csv"
+            path = "balances.csv"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.addr, "127.0.0.1:6000".parse().unwrap());
+        assert_eq!(config.n_assets, 5);
+        assert_eq!(config.commitments, Commitments::PerAsset);
+        assert_eq!(config.log_level().unwrap(), tracing::Level::DEBUG);
+        assert_eq!(
+            config.data_source,
+            DataSource::Csv {
+                path: "balances.csv".to_string()
+            }
+        );
+
+        let overridden = config
+            .clone()
+            .with_env_overrides(|key| match key {
+                "N_ASSETS" => Some("2".to_string()),
+                "ADDR" => Some("0.0.0.0:7000".to_string()),
+                "EPOCH" => Some("4".to_string()),
+                "COMMITMENTS" => Some("total".to_string()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(overridden.n_assets, 2);
+        assert_eq!(overridden.epoch, 4);
+        assert_eq!(overridden.commitments, Commitments::Total);
+        assert!(config.clone().validate().is_err());
+        let opted_in = config
+            .clone()
+            .with_env_overrides(|key| match key {
+                "UNSAFE_ALLOW_UNPROVEN_PER_ASSET" => Some("true".to_string()),
+                _ => None,
+            })
+            .unwrap()
+            .validate()
+            .unwrap();
+        assert_eq!(opted_in.commitments, Commitments::PerAsset);
+        for n_assets in ["0", "9"] {
+            let result = opted_in
+                .clone()
+                .with_env_overrides(|key| match key {
+                    "N_ASSETS" => Some(n_assets.to_string()),
+                    _ => None,
+                })
+                .unwrap()
+                .validate();
+            assert!(matches!(result, Err(ErrorKind::Config(msg)) if msg.contains("n_assets")));
+        }
+        assert_eq!(overridden.data_source, config.data_source);
+        assert_eq!(overridden.addr, "0.0.0.0:7000".parse().unwrap());
+        assert_eq!(overridden.log_level, "debug");
+
+        let db = overridden.db().await.unwrap();
+        assert!(overridden.tree_params(&*db).await.is_err());
+        let dev = Config {
+            dev: true,
+            ..overridden
+        };
+        assert!(dev
+            .tree_params(&*dev.db().await.unwrap())
+            .await
+            .unwrap()
+            .is_demo());
+
+        assert!(Config::from_toml_str("unknown = 1").is_err());
+    }
+
+    #[test]
+    fn data_path_keeps_the_source_kind() {
+        let data_path = |key: &str| (key == "DATA_PATH").then(|| "other".to_string());
+        let csv = Config::default().with_env_overrides(data_path).unwrap();
+        assert_eq!(
+            csv.data_source,
+            DataSource::Csv {
+                path: "other".to_string()
+            }
+        );
+
+        #[cfg(feature = "sqlite")]
+        {
+            let sqlite = Config {
+                data_source: DataSource::Sqlite {
+                    path: "data/oram_smst.sqlite".to_string(),
+                },
+                ..Config::default()
+            };
+            assert_eq!(
+                sqlite.with_env_overrides(data_path).unwrap().data_source,
+                DataSource::Sqlite {
+                    path: "other".to_string()
+                }
+            );
+        }
+    }
+}
@@ -1,45 +1,108 @@
-use std::error::Error;
+use std::{fmt::Debug, pin::Pin};
 
-use csv::Csv;
+use tokio_stream::{Stream, StreamExt};
 
-use crate::{record::Record, tree::TreeParams};
+use crate::{
+    error::{ErrorKind, Result},
+    node_position::NodePosition,
+    nodes::partial::PartialNode,
+    record::Record,
+    tree::{RecordMap, TreeParams, SMT},
+};
 
 pub mod csv;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "rocksdb")]
+pub mod rocksdb;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
-#[derive(Debug)]
-pub enum DBType {
-    /// The path to the csv file to take data from
-    Csv(Csv),
-    RocksDb,
-    Postgres,
+/// A record as read from a storage, the number of balances is checked when it is turned
+/// into a `Record<N_CURR>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredRecord {
+    pub hashed_email: String,
+    pub balances: Vec<u64>,
 }
-impl DBType {
-    pub fn get_tree_params(&self) -> Result<TreeParams, Box<dyn Error>> {
-        match &self {
-            Self::Csv(db) => db.get_tree_params(),
-            _ => todo!("Implement Db"),
-        }
-    }
-
-    pub fn set_record_new_balances<const N_CURR: usize>(
-        &mut self,
-        email: &str,
-        balances: &[String; N_CURR],
-    ) -> Result<(), Box<dyn Error>> {
-        match self {
-            Self::Csv(db) => db.set_record_new_balances(email, balances),
-            _ => todo!("Implement db"),
-        }
+
+impl<const N_CURR: usize> TryFrom<StoredRecord> for Record<N_CURR> {
+    type Error = ErrorKind;
+
+    fn try_from(record: StoredRecord) -> Result<Self> {
+        let balances: [u64; N_CURR] =
+            record.balances.try_into().map_err(|balances: Vec<u64>| {
+                ErrorKind::StorageAssetCount {
+                    given: balances.len(),
+                    expected: N_CURR,
+                }
+            })?;
+        Ok(Record::new(&balances, record.hashed_email))
+    }
+}
+
+pub type RecordStream<'a> = Pin<Box<dyn Stream<Item = Result<StoredRecord>> + Send + 'a>>;
+
+/// Where the records and the tree params are kept, backends are used as `Arc<dyn Storage>`
+#[tonic::async_trait]
+pub trait Storage: Debug + Send + Sync {
+    /// the tree params stored with `set_tree_params`, decrypted with `password`
+    async fn tree_params(&self, password: &[u8]) -> Result<TreeParams>;
+    /// stores the tree params encrypted with `password` in the keyfile format
+    async fn set_tree_params(&self, _tree_params: &TreeParams, _password: &[u8]) -> Result<()> {
+        Err(ErrorKind::DbTreeParamsUnsupported)
+    }
+    /// every record, in the order of the storage
+    async fn records(&self) -> Result<RecordStream<'_>>;
+    /// sets the balances of the user with the plain `email`, inserting the user when missing
+    async fn set_balances(&self, email: &str, balances: &[u64]) -> Result<()>;
+    /// replaces the persisted tree, storages which cannot keep it ignore it
+    async fn save_tree(
+        &self,
+        _tree: &SMT<PartialNode>,
+        _record_map: &RecordMap,
+        _tree_params: &TreeParams,
+    ) -> Result<()> {
+        Ok(())
+    }
+    /// overwrites the given nodes of the persisted tree, the root included
+    async fn save_nodes(&self, _nodes: &[(NodePosition, PartialNode)]) -> Result<()> {
+        Ok(())
+    }
+    /// records the root of a newly built tree, storages without epochs ignore it
+    async fn publish_root(
+        &self,
+        _root: &PartialNode,
+        _n_records: u64,
+        _tree_params: &TreeParams,
+    ) -> Result<()> {
+        Ok(())
+    }
+    /// the root of the last `publish_root`
+    async fn latest_root(&self) -> Result<Option<PartialNode>> {
+        Ok(None)
+    }
+    /// the tree written by `save_tree`, `None` when there is none for these tree params
+    async fn load_tree(
+        &self,
+        _tree_params: &TreeParams,
+    ) -> Result<Option<(SMT<PartialNode>, RecordMap)>> {
+        Ok(None)
+    }
+}
+
+/// reads every record of `storage`, failing when a record does not have `N_CURR` balances
+pub async fn collect_records<const N_CURR: usize>(
+    storage: &dyn Storage,
+) -> Result<Vec<Record<N_CURR>>> {
+    let mut stream = storage.records().await?;
+    let mut records = vec![];
+    while let Some(record) = stream.next().await {
+        records.push(record?.try_into()?);
     }
+    Ok(records)
 }
 
-//TODO: Add More things later
-pub trait DB {
-    fn get_tree_params(&self) -> Result<TreeParams, Box<dyn Error>>;
-    fn get_records<const N_CURR: usize>(&self) -> Result<Vec<Record<N_CURR>>, Box<dyn Error>>;
-    fn set_record_new_balances<const N_CURR: usize>(
-        &mut self,
-        email: &str,
-        balances: &[String; N_CURR],
-    ) -> Result<(), Box<dyn Error>>;
+pub(crate) fn storage_error<E: std::fmt::Display>(err: E) -> ErrorKind {
+    ErrorKind::Storage(err.to_string())
 }
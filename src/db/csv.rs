@@ -1,13 +1,12 @@
 use csv::{ReaderBuilder, WriterBuilder};
 use sha2::Digest;
-use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 
-use crate::error::ErrorKind;
-use crate::record::Record;
+use crate::error::{ErrorKind, Result};
 use crate::tree::TreeParams;
 
-use super::DB;
+use super::{storage_error, RecordStream, Storage, StoredRecord};
+
 #[derive(Debug)]
 pub struct Csv {
     pub file: String,
@@ -19,96 +18,189 @@ impl Default for Csv {
         Self { file: file_path }
     }
 }
-impl DB for Csv {
-    fn get_records<const N_CURR: usize>(&self) -> Result<Vec<Record<N_CURR>>, Box<dyn Error>> {
-        let file = File::open(&self.file)?;
-        let mut rdr = ReaderBuilder::new().from_reader(file);
-        let mut records = vec![];
-        for result in rdr.records() {
-            let record = result?;
+#[tonic::async_trait]
+impl Storage for Csv {
+    // For csv we have hard corded as csv is not recomemded use for production
+    async fn tree_params(&self, _password: &[u8]) -> Result<TreeParams> {
+        Ok(TreeParams::demo())
+    }
+
+    async fn records(&self) -> Result<RecordStream<'_>> {
+        let file = File::open(&self.file).map_err(storage_error)?;
+        let rdr = ReaderBuilder::new().from_reader(file);
+        let records = rdr.into_records().map(|result| {
+            let record = result.map_err(storage_error)?;
             let plain_email = record
                 .get(0)
                 .ok_or(ErrorKind::CsvParserErrorFieldNotFound("email".to_string()))?;
             let hashed_email = hex::encode(sha2::Sha256::digest(plain_email));
-            let mut balances = [0u64; N_CURR];
-            for (i, balance) in record.iter().skip(1).take(N_CURR).enumerate() {
-                balances[i] = balance.parse::<u64>().unwrap();
-            }
-            records.push(Record::new(&balances, hashed_email));
-        }
-
-        Ok(records)
+            let balances = record
+                .iter()
+                .skip(1)
+                .map(|balance| balance.parse::<u64>().map_err(storage_error))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(StoredRecord {
+                hashed_email,
+                balances,
+            })
+        });
+        Ok(Box::pin(tokio_stream::iter(records)))
     }
 
-    fn set_record_new_balances<const N_CURR: usize>(
-        &mut self,
-        email: &str,
-        balances: &[String; N_CURR],
-    ) -> Result<(), Box<dyn Error>> {
-        let file = File::open(&self.file)?;
-        let mut rdr = ReaderBuilder::new().from_reader(file);
-
-        let mut records: Vec<Vec<String>> = Vec::new();
-
-        // Read the CSV into memory
-        for result in rdr.records() {
-            let record = result?;
-            let mut row: Vec<String> = record.iter().map(String::from).collect();
-
-            // If the email matches, update balances
-            if row[0] == email {
-                row.extend_from_slice(balances);
-            }
+    /// updates the balances of an existing user or appends a new one on the blocking thread pool
+    async fn set_balances(&self, email: &str, balances: &[u64]) -> Result<()> {
+        let (file, email, balances) = (self.file.clone(), email.to_string(), balances.to_vec());
+        tokio::task::spawn_blocking(move || update_balances(&file, &email, &balances))
+            .await
+            .map_err(storage_error)?
+    }
+}
 
-            records.push(row);
+/// rewrites `file` with the new balances of `email` to a temporary file which replaces it,
+/// so a failed write never leaves it truncated
+/// the read, modify, write holds an exclusive lock on `<file>.lock` so updates of the same
+/// file never interleave, even from another process
+fn update_balances(file: &str, email: &str, balances: &[u64]) -> Result<()> {
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(format!("{}.lock", file))
+        .map_err(storage_error)?;
+    lock.lock().map_err(storage_error)?;
+    let mut rdr = ReaderBuilder::new()
+        .from_path(file)
+        .map_err(storage_error)?;
+    let headers = rdr.headers().map_err(storage_error)?.clone();
+    if headers.is_empty() {
+        return Err(ErrorKind::CsvParserErrorFieldNotFound("email".to_string()));
+    }
+    if balances.len() != headers.len() - 1 {
+        return Err(ErrorKind::StorageAssetCount {
+            given: balances.len(),
+            expected: headers.len() - 1,
+        });
+    }
+    let new_row: Vec<String> = std::iter::once(email.to_string())
+        .chain(balances.iter().map(u64::to_string))
+        .collect();
+
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut found = false;
+    for result in rdr.records() {
+        let record = result.map_err(storage_error)?;
+        if record.get(0) == Some(email) {
+            rows.push(new_row.clone());
+            found = true;
+        } else {
+            rows.push(record.iter().map(String::from).collect());
         }
+    }
+    if !found {
+        rows.push(new_row);
+    }
 
-        // Write the updated data back to the file
-        let mut wtr = WriterBuilder::new().from_path(&self.file)?;
+    // next to the file so the rename stays on the same filesystem
+    let tmp_path = format!("{}.tmp", file);
+    let mut wtr = WriterBuilder::new()
+        .from_path(&tmp_path)
+        .map_err(storage_error)?;
+    wtr.write_record(&headers).map_err(storage_error)?;
+    for row in rows {
+        wtr.write_record(&row).map_err(storage_error)?;
+    }
+    let tmp_file = wtr.into_inner().map_err(storage_error)?;
+    tmp_file.sync_all().map_err(storage_error)?;
+    fs::rename(&tmp_path, file).map_err(storage_error)
+}
 
-        let mut headers = vec![String::from("UserEmail")];
-        for i in 0..N_CURR {
-            headers.push(format!("Asset_{}", i));
-        }
+#[tokio::test]
+async fn should_parse() {
+    let file_path = format!("{}/data/data.csv", env!("CARGO_MANIFEST_DIR"));
+    let db = Csv { file: file_path };
+    let recs = super::collect_records::<3>(&db).await.unwrap();
+    assert_eq!(recs.len(), 100);
+    assert_eq!(
+        recs[0].hashed_email,
+        hex::encode(sha2::Sha256::digest("a9shgh5zim@gmail.com"))
+    );
+    assert_eq!(recs[0].balances(), &[6989, 8950, 9345]);
+    assert_eq!(recs[99].balances(), &[1146, 8880, 7047]);
+    assert!(super::collect_records::<2>(&db).await.is_err());
+}
 
-        // Write header row
-        wtr.write_record(&headers)?;
+/// a copy of the demo data under a name unique to the test and the process, so concurrent
+/// runs never share a file
+#[cfg(test)]
+fn copy_of_data(name: &str) -> Csv {
+    let file = std::env::temp_dir().join(format!("{}_{}.csv", name, std::process::id()));
+    fs::copy(&Csv::default().file, &file).unwrap();
+    Csv {
+        file: file.to_string_lossy().into_owned(),
+    }
+}
 
-        // Write each record
-        for record in records {
-            wtr.write_record(&record)?;
-        }
+#[tokio::test]
+async fn should_update_existing_user() {
+    let db = copy_of_data("oram_smst_csv_update");
+    let before = super::collect_records::<3>(&db).await.unwrap();
+    db.set_balances("a9shgh5zim@gmail.com", &[1, 2, 3])
+        .await
+        .unwrap();
+
+    let contents = fs::read_to_string(&db.file).unwrap();
+    assert!(contents.starts_with("UserEmail,Asset_1,Asset_2,Asset_3\n"));
+    assert!(contents.contains("a9shgh5zim@gmail.com,1,2,3\n"));
+    let after = super::collect_records::<3>(&db).await.unwrap();
+    assert_eq!(after.len(), before.len());
+    assert_eq!(after[0].balances(), &[1, 2, 3]);
+    assert_eq!(after[1].balances(), before[1].balances());
+    fs::remove_file(&db.file).unwrap();
+    fs::remove_file(format!("{}.lock", db.file)).unwrap();
+}
 
-        wtr.flush()?;
-        Ok(())
-    }
+#[tokio::test]
+async fn should_insert_new_user() {
+    let db = copy_of_data("oram_smst_csv_insert");
+    let before = super::collect_records::<3>(&db).await.unwrap();
+    db.set_balances("new_user@example.com", &[4, 5, 6])
+        .await
+        .unwrap();
+
+    let after = super::collect_records::<3>(&db).await.unwrap();
+    assert_eq!(after.len(), before.len() + 1);
+    assert_eq!(after.last().unwrap().balances(), &[4, 5, 6]);
+    assert!(matches!(
+        db.set_balances("new_user@example.com", &[1, 2]).await,
+        Err(ErrorKind::StorageAssetCount {
+            given: 2,
+            expected: 3
+        })
+    ));
+    assert!(!std::path::Path::new(&format!("{}.tmp", db.file)).exists());
+    fs::remove_file(&db.file).unwrap();
+    fs::remove_file(format!("{}.lock", db.file)).unwrap();
+}
 
-    // For csv we have hard corded as csv is not recomemded use for production
-    fn get_tree_params(&self) -> Result<TreeParams, Box<dyn Error>> {
-        let master_secret: [u8; 32] = "Mina Blockchain enables privacy!"
-            .as_bytes()
-            .try_into()
-            .unwrap();
-        let salt_s: [u8; 32] = "message_encryption_salt_value_12"
-            .as_bytes()
-            .try_into()
-            .unwrap();
-        let salt_b: [u8; 32] = "Bright stars illuminate dark sky"
-            .as_bytes()
-            .try_into()
-            .unwrap();
-        Ok(TreeParams {
-            master_secret: master_secret.into(),
-            salt_b: salt_b.into(),
-            salt_s: salt_s.into(),
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_updates_keep_every_row() {
+    let db = std::sync::Arc::new(copy_of_data("oram_smst_csv_concurrent"));
+    let before = super::collect_records::<3>(&*db).await.unwrap();
+    let updates: Vec<_> = (0..8u64)
+        .map(|i| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                db.set_balances(&format!("user_{}@example.com", i), &[i, i, i])
+                    .await
+            })
         })
+        .collect();
+    for update in updates {
+        update.await.unwrap().unwrap();
     }
-}
 
-#[test]
-fn should_parse() {
-    let file_path = format!("{}/data/data.csv", env!("CARGO_MANIFEST_DIR"));
-    let db = Csv { file: file_path };
-    let recs = db.get_records::<3>().unwrap();
-    println!("Records {:?}", recs)
+    let after = super::collect_records::<3>(&*db).await.unwrap();
+    assert_eq!(after.len(), before.len() + 8);
+    fs::remove_file(&db.file).unwrap();
+    fs::remove_file(format!("{}.lock", db.file)).unwrap();
 }
@@ -0,0 +1,279 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+use sha2::Digest;
+use tokio::sync::{
+    mpsc::{self, Sender},
+    Mutex,
+};
+use tokio_postgres::{types::ToSql, Client, NoTls, Row};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    error::{ErrorKind, Result},
+    tree::TreeParams,
+};
+
+use super::{storage_error, RecordStream, Storage, StoredRecord};
+
+/// Where the records live in the ledger
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostgresConfig {
+    /// connection string, e.g. `host=localhost user=postgres dbname=ledger`
+    pub url: String,
+    pub table: String,
+    /// column of the plain email, must be unique so balances can be upserted
+    pub email_column: String,
+    /// one `BIGINT` column per asset, in the order of the record balances
+    pub asset_columns: Vec<String>,
+    /// replaces the default `SELECT` on the table when reading, it must return the email
+    /// followed by the asset columns
+    #[serde(default)]
+    pub query: Option<String>,
+    /// number of rows fetched at once
+    #[serde(default = "default_page_size")]
+    pub page_size: i32,
+}
+
+fn default_page_size() -> i32 {
+    10_000
+}
+
+/// Records read from and written to a Postgres table
+#[derive(Clone)]
+pub struct Postgres {
+    config: Arc<PostgresConfig>,
+    client: Arc<Mutex<Client>>,
+}
+
+impl std::fmt::Debug for Postgres {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Postgres")
+            .field("table", &self.config.table)
+            .finish()
+    }
+}
+
+impl Postgres {
+    pub async fn connect(config: PostgresConfig) -> Result<Self> {
+        let client = Self::connect_client(&config.url).await?;
+        Ok(Self {
+            config: Arc::new(config),
+            client: Arc::new(Mutex::new(client)),
+        })
+    }
+
+    async fn connect_client(url: &str) -> Result<Client> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls)
+            .await
+            .map_err(storage_error)?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                tracing::error!("postgres connection error {}", err);
+            }
+        });
+        Ok(client)
+    }
+
+    /// reads the records `page_size` rows at a time through a portal and sends them to `tx`,
+    /// the channel is bounded so a slow reader holds back the next page
+    /// the scan runs on its own connection so a slow reader never blocks the updates
+    async fn send_pages(&self, tx: &Sender<Result<StoredRecord>>) -> Result<()> {
+        let mut client = Self::connect_client(&self.config.url).await?;
+        let transaction = client.transaction().await.map_err(storage_error)?;
+        let portal = transaction
+            .bind(&self.select_query(), &[])
+            .await
+            .map_err(storage_error)?;
+        loop {
+            let rows = transaction
+                .query_portal(&portal, self.config.page_size)
+                .await
+                .map_err(storage_error)?;
+            if rows.is_empty() {
+                break;
+            }
+            for row in rows.iter() {
+                let record = row_to_record(row, self.config.asset_columns.len());
+                if tx.send(record).await.is_err() {
+                    // the reader went away
+                    return Ok(());
+                }
+            }
+        }
+        transaction.commit().await.map_err(storage_error)
+    }
+
+    /// upserts the balances of every user in a single transaction
+    pub async fn upsert_balances(&self, updates: &[(String, Vec<u64>)]) -> Result<()> {
+        let mut client = self.client.lock().await;
+        let transaction = client.transaction().await.map_err(storage_error)?;
+        let statement = transaction
+            .prepare(&self.upsert_query())
+            .await
+            .map_err(storage_error)?;
+        for (email, balances) in updates {
+            if balances.len() != self.config.asset_columns.len() {
+                return Err(ErrorKind::StorageAssetCount {
+                    given: balances.len(),
+                    expected: self.config.asset_columns.len(),
+                });
+            }
+            let balances = balances
+                .iter()
+                .map(|&balance| {
+                    i64::try_from(balance).map_err(|_| {
+                        ErrorKind::DbMalformed(format!("balance {} does not fit a BIGINT", balance))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let mut params: Vec<&(dyn ToSql + Sync)> = vec![email as &(dyn ToSql + Sync)];
+            params.extend(balances.iter().map(|b| b as &(dyn ToSql + Sync)));
+            transaction
+                .execute(&statement, &params)
+                .await
+                .map_err(storage_error)?;
+        }
+        transaction.commit().await.map_err(storage_error)
+    }
+
+    fn select_query(&self) -> String {
+        match &self.config.query {
+            Some(query) => query.clone(),
+            None => format!(
+                "SELECT {}, {} FROM {}",
+                quote_ident(&self.config.email_column),
+                self.columns(),
+                quote_ident(&self.config.table)
+            ),
+        }
+    }
+
+    fn upsert_query(&self) -> String {
+        let placeholders = (1..=self.config.asset_columns.len() + 1)
+            .map(|i| format!("${}", i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let updates = self
+            .config
+            .asset_columns
+            .iter()
+            .map(|column| format!("{0} = EXCLUDED.{0}", quote_ident(column)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "INSERT INTO {} ({}, {}) VALUES ({}) ON CONFLICT ({}) DO UPDATE SET {}",
+            quote_ident(&self.config.table),
+            quote_ident(&self.config.email_column),
+            self.columns(),
+            placeholders,
+            quote_ident(&self.config.email_column),
+            updates
+        )
+    }
+
+    fn columns(&self) -> String {
+        self.config
+            .asset_columns
+            .iter()
+            .map(|column| quote_ident(column))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[tonic::async_trait]
+impl Storage for Postgres {
+    // the secrets are not kept next to the ledger, use a keyfile as the secret source
+    async fn tree_params(&self, _password: &[u8]) -> Result<TreeParams> {
+        Err(ErrorKind::DbTreeParamsNotFound)
+    }
+
+    async fn records(&self) -> Result<RecordStream<'_>> {
+        let (tx, rx) = mpsc::channel(self.config.page_size.max(1) as usize);
+        let postgres = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = postgres.send_pages(&tx).await {
+                let _ = tx.send(Err(err)).await;
+            }
+        });
+        Ok(Box::pin(ReceiverStream::new(rx)))
+    }
+
+    async fn set_balances(&self, email: &str, balances: &[u64]) -> Result<()> {
+        self.upsert_balances(&[(email.to_string(), balances.to_vec())])
+            .await
+    }
+}
+
+fn row_to_record(row: &Row, n_assets: usize) -> Result<StoredRecord> {
+    let malformed = |err: tokio_postgres::Error| ErrorKind::DbMalformed(err.to_string());
+    let email: String = row.try_get(0).map_err(malformed)?;
+    let balances = (1..=n_assets)
+        .map(|i| {
+            let value: i64 = row.try_get(i).map_err(malformed)?;
+            u64::try_from(value)
+                .map_err(|_| ErrorKind::DbMalformed(format!("negative balance for {}", email)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(StoredRecord {
+        hashed_email: hex::encode(sha2::Sha256::digest(&email)),
+        balances,
+    })
+}
+
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Postgres, PostgresConfig};
+    use crate::db::{collect_records, Storage};
+
+    /// needs a database, run it with
+    /// `ORAM_SMST_TEST_POSTGRES_URL="host=localhost user=postgres" cargo test --features postgres -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs a postgres database in ORAM_SMST_TEST_POSTGRES_URL"]
+    async fn postgres_round_trip() {
+        let url = std::env::var("ORAM_SMST_TEST_POSTGRES_URL")
+            .expect("ORAM_SMST_TEST_POSTGRES_URL is not set");
+        let config = PostgresConfig {
+            url,
+            table: "oram_smst_test_balances".to_string(),
+            email_column: "email".to_string(),
+            asset_columns: vec!["btc".to_string(), "eth".to_string()],
+            query: None,
+            page_size: 2,
+        };
+        let db = Postgres::connect(config).await.unwrap();
+        db.client
+            .lock()
+            .await
+            .batch_execute(
+                "DROP TABLE IF EXISTS oram_smst_test_balances;
+                CREATE TABLE oram_smst_test_balances (
+                    email TEXT PRIMARY KEY, btc BIGINT NOT NULL, eth BIGINT NOT NULL
+                );",
+            )
+            .await
+            .unwrap();
+
+        let updates: Vec<(String, Vec<u64>)> = (0..5u64)
+            .map(|i| (format!("user_{}@example.com", i), vec![i, 2 * i]))
+            .collect();
+        db.upsert_balances(&updates).await.unwrap();
+        db.set_balances("user_0@example.com", &[7, 8])
+            .await
+            .unwrap();
+
+        // five records over pages of two
+        let records = collect_records::<2>(&db).await.unwrap();
+        assert_eq!(records.len(), 5);
+        assert!(records.iter().any(|record| record.balances() == &[7, 8]));
+        assert!(collect_records::<3>(&db).await.is_err());
+        assert!(db.set_balances("x", &[u64::MAX, 0]).await.is_err());
+        assert!(db.set_balances("x", &[1]).await.is_err());
+    }
+}
@@ -0,0 +1,355 @@
+use std::{fmt::Debug, path::Path, sync::Arc};
+
+use rocksdb::{IteratorMode, Options, WriteBatch, DB as Rocks};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+use crate::{
+    error::{ErrorKind, Result},
+    keyfile,
+    node_position::{Height, NodePosition},
+    nodes::partial::PartialNode,
+    record::Record,
+    store::Store,
+    tree::{RecordMap, TreeParams, SMT},
+};
+
+use super::{storage_error, RecordStream, Storage, StoredRecord};
+
+/// Column family of the balances keyed by the hashed email
+const RECORDS_CF: &str = "records";
+/// Column family of the tree params
+const PARAMS_CF: &str = "params";
+/// Column family of the built tree nodes keyed by their position, the root included
+const NODES_CF: &str = "nodes";
+
+const TREE_PARAMS_KEY: &[u8] = b"tree_params";
+const TREE_KEY: &[u8] = b"tree";
+
+/// Everything of a persisted tree except its nodes
+#[derive(Serialize, Deserialize)]
+struct TreeMeta {
+    params_fingerprint: String,
+    height: Height,
+    store_depth: u8,
+    record_map: RecordMap,
+}
+
+/// Records, tree params and tree nodes stored in a RocksDB database
+/// balances are stored as little endian `u64`s so the number of assets is checked on read
+/// the `Storage` calls run on the blocking thread pool as every rocksdb call is blocking I/O
+pub struct RocksDb {
+    db: Arc<Rocks>,
+}
+
+impl Debug for RocksDb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RocksDb")
+            .field("path", &self.db.path())
+            .finish()
+    }
+}
+
+impl RocksDb {
+    /// opens the database at `path`, creating it and its column families when missing
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let db = Rocks::open_cf(&opts, path, [RECORDS_CF, PARAMS_CF, NODES_CF])
+            .map_err(storage_error)?;
+        Ok(Self { db: Arc::new(db) })
+    }
+
+    /// writes all the records in a single atomic batch
+    pub fn set_records<const N_CURR: usize>(&self, records: &[Record<N_CURR>]) -> Result<()> {
+        let cf = self.cf(RECORDS_CF)?;
+        let mut batch = WriteBatch::default();
+        for record in records {
+            batch.put_cf(
+                cf,
+                record.hashed_email.as_bytes(),
+                encode_balances(record.balances()),
+            );
+        }
+        self.db.write(batch).map_err(storage_error)
+    }
+
+    fn cf(&self, name: &str) -> Result<&rocksdb::ColumnFamily> {
+        cf(&self.db, name)
+    }
+
+    /// runs `f` on the blocking thread pool
+    async fn blocking<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Rocks) -> Result<R> + Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || f(&db))
+            .await
+            .map_err(storage_error)?
+    }
+}
+
+fn cf<'a>(db: &'a Rocks, name: &str) -> Result<&'a rocksdb::ColumnFamily> {
+    db.cf_handle(name)
+        .ok_or_else(|| ErrorKind::DbMalformed(format!("missing column family {}", name)))
+}
+
+#[tonic::async_trait]
+impl Storage for RocksDb {
+    async fn tree_params(&self, password: &[u8]) -> Result<TreeParams> {
+        let password = password.to_vec();
+        self.blocking(move |db| {
+            let value = db
+                .get_cf(cf(db, PARAMS_CF)?, TREE_PARAMS_KEY)
+                .map_err(storage_error)?
+                .ok_or(ErrorKind::DbTreeParamsNotFound)?;
+            keyfile::decrypt(&value, &password)
+        })
+        .await
+    }
+
+    /// the params are kept in the keyfile format so the database never holds them in plain
+    async fn set_tree_params(&self, tree_params: &TreeParams, password: &[u8]) -> Result<()> {
+        let tree_params = tree_params.clone();
+        let password = password.to_vec();
+        self.blocking(move |db| {
+            let value = keyfile::encrypt(&tree_params, &password)?;
+            db.put_cf(cf(db, PARAMS_CF)?, TREE_PARAMS_KEY, value)
+                .map_err(storage_error)
+        })
+        .await
+    }
+
+    // the rocksdb iterators are not `Send` so the records are read before streaming them
+    async fn records(&self) -> Result<RecordStream<'_>> {
+        let records = self
+            .blocking(|db| {
+                Ok(db
+                    .iterator_cf(cf(db, RECORDS_CF)?, IteratorMode::Start)
+                    .map(|item| {
+                        let (key, value) = item.map_err(storage_error)?;
+                        Ok(StoredRecord {
+                            hashed_email: String::from_utf8(key.to_vec())
+                                .map_err(|err| ErrorKind::DbMalformed(err.to_string()))?,
+                            balances: decode_balances(&value)?,
+                        })
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .await?;
+        Ok(Box::pin(tokio_stream::iter(records)))
+    }
+
+    async fn set_balances(&self, email: &str, balances: &[u64]) -> Result<()> {
+        let hashed_email = hex::encode(sha2::Sha256::digest(email));
+        let balances = encode_balances(balances);
+        self.blocking(move |db| {
+            db.put_cf(cf(db, RECORDS_CF)?, hashed_email.as_bytes(), balances)
+                .map_err(storage_error)
+        })
+        .await
+    }
+    async fn save_tree(
+        &self,
+        tree: &SMT<PartialNode>,
+        record_map: &RecordMap,
+        tree_params: &TreeParams,
+    ) -> Result<()> {
+        let meta = serde_json::to_vec(&TreeMeta {
+            params_fingerprint: tree_params.fingerprint(),
+            height: tree.height,
+            store_depth: tree.store_depth,
+            record_map: record_map.clone(),
+        })
+        .map_err(storage_error)?;
+        let root = (NodePosition::new(0, tree.height), tree.root.clone());
+        let nodes = tree
+            .store
+            .map
+            .iter()
+            .map(|(pos, node)| (*pos, node.clone()))
+            .chain(std::iter::once(root))
+            .map(|(pos, node)| Ok((position_key(&pos), serde_json::to_vec(&node)?)))
+            .collect::<std::result::Result<Vec<_>, serde_json::Error>>()
+            .map_err(storage_error)?;
+        self.blocking(move |db| {
+            let nodes_cf = cf(db, NODES_CF)?;
+            let mut batch = WriteBatch::default();
+            for item in db.iterator_cf(nodes_cf, IteratorMode::Start) {
+                let (key, _) = item.map_err(storage_error)?;
+                batch.delete_cf(nodes_cf, key);
+            }
+            for (key, node) in nodes {
+                batch.put_cf(nodes_cf, key, node);
+            }
+            batch.put_cf(cf(db, PARAMS_CF)?, TREE_KEY, meta);
+            db.write(batch).map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn save_nodes(&self, nodes: &[(NodePosition, PartialNode)]) -> Result<()> {
+        let nodes = nodes
+            .iter()
+            .map(|(pos, node)| Ok((position_key(pos), serde_json::to_vec(node)?)))
+            .collect::<std::result::Result<Vec<_>, serde_json::Error>>()
+            .map_err(storage_error)?;
+        self.blocking(move |db| {
+            let nodes_cf = cf(db, NODES_CF)?;
+            let mut batch = WriteBatch::default();
+            for (key, node) in nodes {
+                batch.put_cf(nodes_cf, key, node);
+            }
+            db.write(batch).map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn load_tree(
+        &self,
+        tree_params: &TreeParams,
+    ) -> Result<Option<(SMT<PartialNode>, RecordMap)>> {
+        let fingerprint = tree_params.fingerprint();
+        self.blocking(move |db| {
+            let Some(meta) = db
+                .get_cf(cf(db, PARAMS_CF)?, TREE_KEY)
+                .map_err(storage_error)?
+            else {
+                return Ok(None);
+            };
+            let meta: TreeMeta = serde_json::from_slice(&meta)
+                .map_err(|err| ErrorKind::DbMalformed(err.to_string()))?;
+            if meta.params_fingerprint != fingerprint {
+                tracing::warn!("the persisted tree was built with other tree params");
+                return Ok(None);
+            }
+            let mut store = Store::new();
+            for item in db.iterator_cf(cf(db, NODES_CF)?, IteratorMode::Start) {
+                let (key, value) = item.map_err(storage_error)?;
+                let node = serde_json::from_slice(&value)
+                    .map_err(|err| ErrorKind::DbMalformed(err.to_string()))?;
+                store.map.insert(decode_position(&key)?, node);
+            }
+            let root = store
+                .map
+                .remove(&NodePosition::new(0, meta.height))
+                .ok_or_else(|| ErrorKind::DbMalformed("missing tree root".to_string()))?;
+            let tree = SMT {
+                root,
+                store,
+                height: meta.height,
+                store_depth: meta.store_depth,
+            };
+            Ok(Some((tree, meta.record_map)))
+        })
+        .await
+    }
+}
+
+fn encode_balances(balances: &[u64]) -> Vec<u8> {
+    balances.iter().flat_map(|b| b.to_le_bytes()).collect()
+}
+
+fn decode_balances(bytes: &[u8]) -> Result<Vec<u64>> {
+    if bytes.len() % 8 != 0 {
+        return Err(ErrorKind::DbMalformed(format!(
+            "balances of {} bytes",
+            bytes.len()
+        )));
+    }
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
+
+/// `height | x` so the nodes of a level are stored together
+fn position_key(pos: &NodePosition) -> [u8; 9] {
+    let mut key = [0u8; 9];
+    key[0] = pos.1.as_u8();
+    key[1..].copy_from_slice(&pos.0.to_be_bytes());
+    key
+}
+
+fn decode_position(key: &[u8]) -> Result<NodePosition> {
+    if key.len() != 9 {
+        return Err(ErrorKind::DbMalformed("wrong node key length".to_string()));
+    }
+    let x = u64::from_be_bytes(key[1..].try_into().unwrap());
+    Ok(NodePosition::new(x, Height::new(key[0])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RocksDb;
+    use crate::{
+        db::{collect_records, Storage},
+        keyfile::generate_tree_params,
+        node_position::{Height, NodePosition},
+        nodes::partial::PartialNode,
+        record::random_records,
+        tree::TreeBuilder,
+    };
+
+    #[tokio::test]
+    async fn rocksdb_round_trip() {
+        let path = std::env::temp_dir().join("oram_smst_rocksdb_round_trip");
+        let _ = std::fs::remove_dir_all(&path);
+        let db = RocksDb::open(&path).unwrap();
+
+        let tree_params = generate_tree_params();
+        db.set_tree_params(&tree_params, b"password").await.unwrap();
+        assert_eq!(
+            db.tree_params(b"password").await.unwrap().fingerprint(),
+            tree_params.fingerprint()
+        );
+        assert!(db.tree_params(b"wrong password").await.is_err());
+
+        let records = random_records::<3>(8);
+        db.set_records(&records).unwrap();
+        assert_eq!(collect_records::<3>(&db).await.unwrap().len(), 8);
+        assert!(collect_records::<2>(&db).await.is_err());
+
+        db.set_balances("new_user@example.com", &[1, 2, 3])
+            .await
+            .unwrap();
+        let records = collect_records::<3>(&db).await.unwrap();
+        assert_eq!(records.len(), 9);
+
+        let mut tree_builder: TreeBuilder<PartialNode, 3> =
+            TreeBuilder::new(records, Height::new(4), tree_params.clone());
+        let (tree, record_map) = tree_builder.build_single_threaded(Some(4)).unwrap();
+        assert!(db.load_tree(&tree_params).await.unwrap().is_none());
+        db.save_tree(&tree, &record_map, &tree_params)
+            .await
+            .unwrap();
+        let (loaded, loaded_record_map) = db.load_tree(&tree_params).await.unwrap().unwrap();
+        assert_eq!(loaded.root, tree.root);
+        assert_eq!(loaded.store.map, tree.store.map);
+        assert_eq!(loaded_record_map, record_map);
+        assert!(db
+            .load_tree(&generate_tree_params())
+            .await
+            .unwrap()
+            .is_none());
+
+        // a new root replaces the stored one
+        let leaf = tree
+            .store
+            .map
+            .iter()
+            .next()
+            .map(|(pos, node)| (*pos, node.clone()))
+            .unwrap();
+        let root = (NodePosition::new(0, Height::new(4)), leaf.1.clone());
+        db.save_nodes(&[leaf, root.clone()]).await.unwrap();
+        let (loaded, _) = db.load_tree(&tree_params).await.unwrap().unwrap();
+        assert_eq!(loaded.root, root.1);
+
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
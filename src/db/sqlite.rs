@@ -0,0 +1,388 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use prost::Message;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::Digest;
+
+use crate::{
+    error::{ErrorKind, Result},
+    keyfile,
+    nodes::partial::PartialNode,
+    record::Record,
+    smt::NodeContent,
+    tree::TreeParams,
+};
+
+use super::{storage_error, RecordStream, Storage, StoredRecord};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY,
+    hashed_email TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS balances (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    asset INTEGER NOT NULL,
+    amount INTEGER NOT NULL CHECK (amount >= 0),
+    PRIMARY KEY (user_id, asset)
+);
+CREATE TABLE IF NOT EXISTS tree_params (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    keyfile BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS epochs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    root BLOB NOT NULL,
+    n_records INTEGER NOT NULL,
+    tree_params_fingerprint TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+";
+
+/// A published tree, the root is the encoded `NodeContent` of the root
+#[derive(Clone, Debug, PartialEq)]
+pub struct Epoch {
+    pub id: i64,
+    pub root: Vec<u8>,
+    pub n_records: u64,
+    pub tree_params_fingerprint: String,
+    /// unix seconds
+    pub created_at: u64,
+}
+
+/// Users, per asset balances, tree params and published epochs in a single SQLite file
+/// balances are `INTEGER`s so they must fit in an `i64`
+/// the connection is only locked for the duration of a query, never across an await
+/// the `Storage` calls lock it and query on the blocking thread pool
+#[derive(Debug)]
+pub struct Sqlite {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Sqlite {
+    /// opens the database at `path` and creates the schema when missing
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::with_connection(Connection::open(path).map_err(storage_error)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory().map_err(storage_error)?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")
+            .map_err(storage_error)?;
+        conn.execute_batch(SCHEMA).map_err(storage_error)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn conn(&self) -> Result<MutexGuard<'_, Connection>> {
+        lock(&self.conn)
+    }
+
+    /// runs `f` with the locked connection on the blocking thread pool
+    async fn blocking<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<R> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut *lock(&conn)?))
+            .await
+            .map_err(storage_error)?
+    }
+
+    /// upserts the balances of every record in a single transaction
+    pub fn set_records<const N_CURR: usize>(&self, records: &[Record<N_CURR>]) -> Result<()> {
+        let records: Vec<(&str, &[u64])> = records
+            .iter()
+            .map(|record| (record.hashed_email.as_str(), record.balances().as_slice()))
+            .collect();
+        upsert(&mut self.conn()?, &records)
+    }
+
+    /// records a published root along with the params it was built with
+    pub fn add_epoch(&self, root: &[u8], n_records: u64, tree_params: &TreeParams) -> Result<i64> {
+        insert_epoch(&self.conn()?, root, n_records, &tree_params.fingerprint())
+    }
+
+    pub fn latest_epoch(&self) -> Result<Option<Epoch>> {
+        select_latest_epoch(&self.conn()?)
+    }
+}
+
+#[tonic::async_trait]
+impl Storage for Sqlite {
+    async fn tree_params(&self, password: &[u8]) -> Result<TreeParams> {
+        let password = password.to_vec();
+        self.blocking(move |conn| {
+            let keyfile: Vec<u8> = conn
+                .query_row("SELECT keyfile FROM tree_params WHERE id = 1", [], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .map_err(storage_error)?
+                .ok_or(ErrorKind::DbTreeParamsNotFound)?;
+            keyfile::decrypt(&keyfile, &password)
+        })
+        .await
+    }
+
+    /// the params are kept in the keyfile format so the database never holds them in plain
+    async fn set_tree_params(&self, tree_params: &TreeParams, password: &[u8]) -> Result<()> {
+        let tree_params = tree_params.clone();
+        let password = password.to_vec();
+        self.blocking(move |conn| {
+            let keyfile = keyfile::encrypt(&tree_params, &password)?;
+            conn.execute(
+                "INSERT OR REPLACE INTO tree_params (id, keyfile) VALUES (1, ?1)",
+                [keyfile],
+            )
+            .map_err(storage_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    // the rows borrow the locked connection so they are read before streaming them
+    async fn records(&self) -> Result<RecordStream<'_>> {
+        let records = self.blocking(|conn| read_records(conn)).await?;
+        Ok(Box::pin(tokio_stream::iter(records.into_iter().map(Ok))))
+    }
+
+    async fn set_balances(&self, email: &str, balances: &[u64]) -> Result<()> {
+        let hashed_email = hex::encode(sha2::Sha256::digest(email));
+        let balances = balances.to_vec();
+        self.blocking(move |conn| upsert(conn, &[(hashed_email.as_str(), balances.as_slice())]))
+            .await
+    }
+    async fn publish_root(
+        &self,
+        root: &PartialNode,
+        n_records: u64,
+        tree_params: &TreeParams,
+    ) -> Result<()> {
+        let root: NodeContent = root.clone().into();
+        let root = root.encode_to_vec();
+        let fingerprint = tree_params.fingerprint();
+        self.blocking(move |conn| insert_epoch(conn, &root, n_records, &fingerprint))
+            .await?;
+        Ok(())
+    }
+
+    async fn latest_root(&self) -> Result<Option<PartialNode>> {
+        let Some(epoch) = self.blocking(|conn| select_latest_epoch(conn)).await? else {
+            return Ok(None);
+        };
+        let content = NodeContent::decode(epoch.root.as_slice())
+            .map_err(|err| ErrorKind::DbMalformed(err.to_string()))?;
+        PartialNode::try_from(content).map(Some)
+    }
+}
+
+fn lock(conn: &Mutex<Connection>) -> Result<MutexGuard<'_, Connection>> {
+    conn.lock()
+        .map_err(|_| ErrorKind::Storage("poisoned sqlite connection".to_string()))
+}
+
+/// upserts the balances of every record in a single transaction
+fn upsert(conn: &mut Connection, records: &[(&str, &[u64])]) -> Result<()> {
+    let transaction = conn.transaction().map_err(storage_error)?;
+    {
+        let mut user = transaction
+            .prepare(
+                "INSERT INTO users (hashed_email) VALUES (?1)
+            ON CONFLICT (hashed_email) DO UPDATE SET hashed_email = excluded.hashed_email
+            RETURNING id",
+            )
+            .map_err(storage_error)?;
+        let mut balance = transaction
+            .prepare(
+                "INSERT INTO balances (user_id, asset, amount) VALUES (?1, ?2, ?3)
+            ON CONFLICT (user_id, asset) DO UPDATE SET amount = excluded.amount",
+            )
+            .map_err(storage_error)?;
+        let mut stale = transaction
+            .prepare("DELETE FROM balances WHERE user_id = ?1 AND asset >= ?2")
+            .map_err(storage_error)?;
+        for (hashed_email, balances) in records {
+            let user_id: i64 = user
+                .query_row([hashed_email], |row| row.get(0))
+                .map_err(storage_error)?;
+            for (asset, &amount) in balances.iter().enumerate() {
+                let amount = i64::try_from(amount).map_err(|_| {
+                    ErrorKind::DbMalformed(format!("balance {} does not fit an INTEGER", amount))
+                })?;
+                balance
+                    .execute(params![user_id, asset as i64, amount])
+                    .map_err(storage_error)?;
+            }
+            // a user with fewer assets than before keeps none of the extra ones
+            stale
+                .execute(params![user_id, balances.len() as i64])
+                .map_err(storage_error)?;
+        }
+    }
+    transaction.commit().map_err(storage_error)
+}
+
+fn read_records(conn: &Connection) -> Result<Vec<StoredRecord>> {
+    let mut statement = conn
+        .prepare(
+            "SELECT users.hashed_email, balances.asset, balances.amount
+            FROM users LEFT JOIN balances ON balances.user_id = users.id
+            ORDER BY users.id, balances.asset",
+        )
+        .map_err(storage_error)?;
+    let mut rows = statement.query([]).map_err(storage_error)?;
+    let mut records: Vec<StoredRecord> = vec![];
+    while let Some(row) = rows.next().map_err(storage_error)? {
+        let hashed_email: String = row.get(0).map_err(storage_error)?;
+        if records
+            .last()
+            .map(|last| last.hashed_email != hashed_email)
+            .unwrap_or(true)
+        {
+            records.push(StoredRecord {
+                hashed_email: hashed_email.clone(),
+                balances: vec![],
+            });
+        }
+        let asset: Option<i64> = row.get(1).map_err(storage_error)?;
+        let amount: Option<i64> = row.get(2).map_err(storage_error)?;
+        let (Some(asset), Some(amount)) = (asset, amount) else {
+            continue;
+        };
+        let balances = &mut records.last_mut().unwrap().balances;
+        // assets are numbered from 0 without gaps
+        if asset != balances.len() as i64 {
+            return Err(ErrorKind::DbMalformed(format!(
+                "missing asset {} of {}",
+                balances.len(),
+                hashed_email
+            )));
+        }
+        balances.push(amount as u64);
+    }
+    Ok(records)
+}
+
+fn insert_epoch(conn: &Connection, root: &[u8], n_records: u64, fingerprint: &str) -> Result<i64> {
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(storage_error)?
+        .as_secs();
+    conn.execute(
+        "INSERT INTO epochs (root, n_records, tree_params_fingerprint, created_at)
+        VALUES (?1, ?2, ?3, ?4)",
+        params![root, n_records as i64, fingerprint, created_at as i64],
+    )
+    .map_err(storage_error)?;
+    Ok(conn.last_insert_rowid())
+}
+
+fn select_latest_epoch(conn: &Connection) -> Result<Option<Epoch>> {
+    conn.query_row(
+        "SELECT id, root, n_records, tree_params_fingerprint, created_at
+        FROM epochs ORDER BY id DESC LIMIT 1",
+        [],
+        |row| {
+            Ok(Epoch {
+                id: row.get(0)?,
+                root: row.get(1)?,
+                n_records: row.get::<_, i64>(2)? as u64,
+                tree_params_fingerprint: row.get(3)?,
+                created_at: row.get::<_, i64>(4)? as u64,
+            })
+        },
+    )
+    .optional()
+    .map_err(storage_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::Sqlite;
+    use crate::{
+        db::{collect_records, Storage},
+        keyfile::generate_tree_params,
+        node_position::{Height, NodePosition},
+        nodes::partial::PartialNode,
+        proofs::MerkleWitness,
+        record::Record,
+        smt::NodeContent,
+        tree::TreeBuilder,
+    };
+
+    #[tokio::test]
+    async fn sqlite_proof_flow() {
+        let db = Sqlite::open_in_memory().unwrap();
+        assert!(db.tree_params(b"password").await.is_err());
+        let tree_params = generate_tree_params();
+        db.set_tree_params(&tree_params, b"password").await.unwrap();
+        assert_eq!(
+            db.tree_params(b"password").await.unwrap().fingerprint(),
+            tree_params.fingerprint()
+        );
+
+        let records: Vec<Record<3>> = (1..=5u64)
+            .map(|i| Record::new(&[i, 2 * i, 3 * i], format!("user_{}", i)))
+            .collect();
+        db.set_records(&records).unwrap();
+        db.set_balances("alice@example.com", &[10, 0, 5])
+            .await
+            .unwrap();
+        db.set_records(&[Record::new(&[9, 9, 9], "user_1".to_string())])
+            .unwrap();
+
+        let records = collect_records::<3>(&db).await.unwrap();
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[0].balances(), &[9, 9, 9]);
+        assert!(collect_records::<2>(&db).await.is_err());
+
+        let tree_params = db.tree_params(b"password").await.unwrap();
+        let mut tree_builder: TreeBuilder<PartialNode, 3> =
+            TreeBuilder::new(records.clone(), Height::new(4), tree_params.clone());
+        let (tree, record_map) = tree_builder.build_single_threaded(None).unwrap();
+        let padding_fn = |pos: &NodePosition| tree_params.padding_node_content(pos);
+        let witness: MerkleWitness<PartialNode, 3> = MerkleWitness::generate_witness(
+            records[2].hashed_email.clone(),
+            &tree,
+            &record_map,
+            &padding_fn,
+        )
+        .unwrap();
+        witness.verify(&tree.root).unwrap();
+
+        let root: NodeContent = tree.root.clone().into();
+        let id = db
+            .add_epoch(&root.encode_to_vec(), records.len() as u64, &tree_params)
+            .unwrap();
+        let epoch = db.latest_epoch().unwrap().unwrap();
+        assert_eq!(epoch.id, id);
+        assert_eq!(epoch.root, root.encode_to_vec());
+        assert_eq!(epoch.tree_params_fingerprint, tree_params.fingerprint());
+        assert_eq!(db.latest_root().await.unwrap(), Some(tree.root.clone()));
+        db.publish_root(&tree.root, records.len() as u64, &tree_params)
+            .await
+            .unwrap();
+        assert_eq!(db.latest_epoch().unwrap().unwrap().id, id + 1);
+
+        // fewer assets than before leave no stale balances behind
+        let db = Sqlite::open_in_memory().unwrap();
+        db.set_records(&[Record::new(&[1, 2, 3], "user".to_string())])
+            .unwrap();
+        db.set_records(&[Record::new(&[4, 5], "user".to_string())])
+            .unwrap();
+        let records = collect_records::<2>(&db).await.unwrap();
+        assert_eq!(records[0].balances(), &[4, 5]);
+    }
+}
@@ -34,6 +34,115 @@ pub enum ErrorKind {
 
     #[error("File {0} not found")]
     CsvParserErrorFiledNotFound(String),
+
+    #[error("Cannot find the block for node at {0} in the ORAM")]
+    OramBlockNotFound(NodePosition),
+
+    #[error("ORAM stash overflowed with {0} blocks")]
+    OramStashOverflow(usize),
+
+    #[error(
+        "The ORAM tree needs every level stored (store depth: {store_depth:?}, height: {height:?})"
+    )]
+    OramPartialStore { store_depth: u8, height: u8 },
+
+    #[error("Cannot read or write the snapshot: {0}")]
+    SnapshotIo(String),
+
+    #[error("Cannot parse the snapshot: {0}")]
+    SnapshotMalformed(String),
+
+    #[error("Unsupported snapshot version (found: {found:?}, expected: {expected:?})")]
+    SnapshotVersionMismatch { found: u32, expected: u32 },
+
+    #[error("The snapshot was built with different tree params")]
+    SnapshotParamsMismatch,
+
+    #[error("The snapshot nodes do not match the recomputed tree")]
+    SnapshotIntegrity,
+
+    #[error("Cannot read the proof: {0}")]
+    CannotReadProof(String),
+
+    #[error("Cannot decode the proof: {0}")]
+    ProofMalformed(String),
+
+    #[error("The proof is missing the field {0}")]
+    ProofMissingField(String),
+
+    #[error("Wrong path length in proof (given: {given:?}, max: {max:?})")]
+    ProofWrongPathLength { given: usize, max: usize },
+
+    #[error("Number of lefts does not match the path (lefts: {lefts:?}, path: {path:?})")]
+    ProofLeftsMismatch { lefts: usize, path: usize },
+
+    #[error("The root computed from the proof does not match the expected root")]
+    ProofRootMismatch,
+
+    #[error("The commitment in the proof is not a valid curve point")]
+    ProofMalformedCurvePoint,
+
+    #[error("The hash in the proof is not a valid field element")]
+    ProofMalformedHash,
+
+    #[error("The blinding factor in the proof is not a valid scalar")]
+    ProofMalformedBlindingFactor,
+
+    #[error("The proof uses the {given} commitment scheme, expected {expected}")]
+    ProofSchemeMismatch { given: String, expected: String },
+
+    #[error("The user node does not open to the given balances")]
+    ProofOpeningMismatch,
+
+    #[error("The committed value does not fit in 64 bits")]
+    RangeProofValueOutOfRange,
+
+    #[error("The range proof does not verify for the commitment")]
+    RangeProofInvalid,
+
+    #[error("Wrong number of range proofs (given: {given:?}, expected: {expected:?})")]
+    RangeProofMissing { given: usize, expected: usize },
+
+    #[error("The range proof is not made of valid curve points and scalars")]
+    RangeProofMalformed,
+
+    #[error("Range check circuit error: {0}")]
+    RangeCheckCircuit(String),
+
+    #[error("The liability and blinding factor do not open the commitment")]
+    RangeCheckOpeningMismatch,
+
+    #[error("The range check proof does not verify for the commitment: {0}")]
+    RangeCheckProofInvalid(String),
+
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    #[error("Keyfile error: {0}")]
+    Keyfile(String),
+
+    #[error("Cannot decrypt the keyfile, wrong password or corrupted file")]
+    KeyfileDecryption,
+
+    #[error("Refusing to use the demo secrets without the dev flag")]
+    DemoSecrets,
+
+    #[error("Malformed data in the database: {0}")]
+    DbMalformed(String),
+
+    #[error("The tree params are not stored in the database")]
+    DbTreeParamsNotFound,
+
+    #[error("The database cannot store the tree params, use a keyfile")]
+    DbTreeParamsUnsupported,
+
+    #[error("Storage error: {0}")]
+    Storage(String),
+
+    #[error(
+        "Wrong number of balances in a stored record (given: {given:?}, expected: {expected:?})"
+    )]
+    StorageAssetCount { given: usize, expected: usize },
 }
 
 pub(crate) type Result<T> = std::result::Result<T, ErrorKind>;
@@ -1,63 +1,193 @@
-use crate::db::csv::Csv;
-use crate::db::{DBType, DB};
+use crate::db::{collect_records, Storage};
+use crate::error::ErrorKind;
 use crate::node_position::NodePosition;
-use crate::nodes::partial::PartialNode;
+use crate::nodes::{node::Node, partial::PartialNode, ServedNode};
+use crate::path_oram::OramTree;
 use crate::proofs::MerkleWitness;
+use crate::record::Record;
 use crate::smt::smt_backend_server::SmtBackend;
 use crate::smt::{
     NodeContent, Proof, RequestProof, Response as SetRecordResponse, SetRecordRequest,
 };
-use crate::tree::{self, new_padding_node_content, TreeBuilder};
+use crate::tree::{
+    new_leaf_node, new_leaf_nodes, new_padding_node_content, RecordMap, TreeBuilder, TreeParams,
+    SMT,
+};
+use crate::tree_builder::single::single_threaded_tree_builder;
+use crate::ScalarField;
+use ark_serialize::CanonicalSerialize;
+use rand::{rngs::StdRng, SeedableRng};
 use sha2::Digest;
 use tonic::{Request, Response, Status};
 
-use std::sync::{Arc, Mutex};
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::Mutex;
 
 const USER_NOT_FOUND: &str = "USER NOT FOUND";
-const SERVER_ERROR: &str = "SERVER ERROR";
 
+/// Version of the `Proof` message, 2 replaced the master secret with the user leaf secrets,
+/// 3 uses version 1 of the hashing scheme in `hasher`, 4 sends the blinding factor as a scalar,
+/// 5 attaches the range proofs of the user node and the path and 6 announces the commitment scheme
+pub const PROOF_PROTOCOL_VERSION: u32 = 6;
+
+/// the uncompressed little endian bytes of a scalar, the encoding of the proof messages
+fn encode_scalar(scalar: &ScalarField) -> Result<Vec<u8>, Status> {
+    let mut bytes = vec![];
+    scalar
+        .serialize_uncompressed(&mut bytes)
+        .map_err(|err| Status::internal(err.to_string()))?;
+    Ok(bytes)
+}
+
+/// The tree built at startup which is kept in memory and updated in place
+/// the nodes are only read and written through the ORAM so the accesses do not reveal the user
+/// `Node`s keep their openings to prove the range of their commitments, only the partial
+/// nodes are persisted
 #[derive(Debug)]
-pub struct Server<const N_CURR: usize> {
-    db: Arc<Mutex<DBType>>,
-    tree_builder: Arc<Mutex<TreeBuilder<PartialNode, N_CURR>>>,
+struct LiveTree<T: ServedNode> {
+    tree: OramTree<T>,
+    record_map: RecordMap,
 }
 
-impl<const N_CURR: usize> Default for Server<N_CURR> {
-    fn default() -> Self {
-        let csv_db = Csv::default();
-        let tree_params = csv_db.get_tree_params().unwrap();
-        let records = csv_db.get_records::<N_CURR>().unwrap();
-        let tree_builder = TreeBuilder::from_records(records, tree_params);
-        Self {
-            db: Arc::new(Mutex::new(DBType::Csv(csv_db))),
-            tree_builder: Arc::new(Mutex::new(tree_builder)),
+/// The storage is shared without a lock, only the live tree is behind an async mutex
+/// the leaves commit to the total liability with `Node` or to every asset with `VectorNode`
+#[derive(Debug)]
+pub struct Server<const N_CURR: usize, T: ServedNode = Node> {
+    db: Arc<dyn Storage>,
+    tree_params: TreeParams,
+    /// epoch of the placement seed used whenever the tree is rebuilt
+    epoch: u64,
+    live_tree: Arc<Mutex<LiveTree<T>>>,
+}
+
+impl<const N_CURR: usize, T: ServedNode> Server<N_CURR, T> {
+    /// restores the tree persisted by `db` or builds it from the records of `db`
+    /// the persisted tree is kept in sync with the records by `set_user_data`
+    pub async fn new(
+        db: Arc<dyn Storage>,
+        tree_params: TreeParams,
+        epoch: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let restored = match db.load_tree(&tree_params).await? {
+            Some((tree, record_map)) => {
+                Self::restore_tree(&*db, &tree_params, tree, record_map).await?
+            }
+            None => None,
+        };
+        let live_tree = match restored {
+            Some(live_tree) => {
+                tracing::info!("restored the persisted tree");
+                live_tree
+            }
+            None => {
+                let live_tree = Self::build_tree(&*db, &tree_params, epoch).await?;
+                live_tree.publish_root(&*db, &tree_params).await?;
+                live_tree
+            }
+        };
+        Ok(Self {
+            db,
+            tree_params,
+            epoch,
+            live_tree: Arc::new(Mutex::new(live_tree)),
+        })
+    }
+
+    /// builds the tree from every record of the storage and persists it, the root is published
+    /// by the caller once the tree is live
+    async fn build_tree(
+        db: &dyn Storage,
+        tree_params: &TreeParams,
+        epoch: u64,
+    ) -> crate::error::Result<LiveTree<T>> {
+        let records = collect_records::<N_CURR>(db).await?;
+        let mut tree_builder: TreeBuilder<T, N_CURR> =
+            TreeBuilder::from_records(records, tree_params.clone()).with_epoch(epoch);
+        // every level is stored so an update only recomputes the path of the leaf
+        let store_depth = tree_builder.height().as_u8();
+        let (tree, record_map) = tree_builder.build_single_threaded(Some(store_depth))?;
+        db.save_tree(&tree.to_partial(), &record_map, tree_params)
+            .await?;
+        LiveTree::new(tree, record_map)
+    }
+
+    /// recomputes the openings of the persisted tree from the records of the storage
+    /// the leaves are rebuilt at their persisted positions, `None` if the records or the root
+    /// do not match the persisted tree
+    async fn restore_tree(
+        db: &dyn Storage,
+        tree_params: &TreeParams,
+        persisted: SMT<PartialNode>,
+        record_map: RecordMap,
+    ) -> crate::error::Result<Option<LiveTree<T>>> {
+        let records = collect_records::<N_CURR>(db).await?;
+        let x_cords: Option<Vec<u64>> = records
+            .iter()
+            .map(|record| record_map.get(&record.hashed_email).map(|pos| pos.x_cord()))
+            .collect();
+        let x_cords = match x_cords {
+            Some(x_cords) if x_cords.len() == record_map.len() => x_cords,
+            _ => {
+                tracing::warn!("the persisted tree does not match the records, rebuilding");
+                return Ok(None);
+            }
+        };
+        let mut leaf_nodes = new_leaf_nodes::<T, N_CURR>(tree_params, &records, &x_cords);
+        leaf_nodes.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        let padding_fn = |pos: &NodePosition| tree_params.padding_node_content(pos);
+        let tree = single_threaded_tree_builder(
+            leaf_nodes,
+            &persisted.height,
+            persisted.store_depth,
+            &padding_fn,
+        )?;
+        if tree.root.to_partial() != persisted.root {
+            tracing::warn!("the persisted root does not match the records, rebuilding");
+            return Ok(None);
         }
+        LiveTree::new(tree, record_map).map(Some)
+    }
+}
+
+impl<T: ServedNode> LiveTree<T> {
+    fn new(tree: SMT<T>, record_map: RecordMap) -> crate::error::Result<Self> {
+        Ok(Self {
+            tree: OramTree::from_tree(tree, StdRng::from_os_rng())?,
+            record_map,
+        })
+    }
+
+    /// publishes the root the proofs are bound to, whenever it changes and only once it is
+    /// persisted, whether the tree was built or updated in place
+    async fn publish_root(
+        &self,
+        db: &dyn Storage,
+        tree_params: &TreeParams,
+    ) -> crate::error::Result<()> {
+        let n_records = self.record_map.len() as u64;
+        db.publish_root(&self.tree.root.to_partial(), n_records, tree_params)
+            .await
     }
 }
 
 #[tonic::async_trait]
-impl<const N_CURR: usize> SmtBackend for Server<N_CURR> {
+impl<const N_CURR: usize, T: ServedNode> SmtBackend for Server<N_CURR, T> {
     async fn generate_proof(
         &self,
         request: Request<RequestProof>,
     ) -> Result<Response<Proof>, Status> {
         let request = request.into_inner();
-        let mut tree_builder = self
-            .tree_builder
-            .lock()
-            .map_err(|_| Status::aborted(SERVER_ERROR))?;
-        let db = self.db.lock().map_err(|_| Status::aborted(SERVER_ERROR))?;
-        let tree_params = db.get_tree_params().unwrap();
-        let (tree, record_map) = tree_builder
-            .build_single_threaded(None)
-            .map_err(|err| Status::aborted(err.to_string()))?;
-        tracing::debug!("user email {}", request.user_email);
+        let mut live_tree = self.live_tree.lock().await;
+        let tree_params = &self.tree_params;
+        let LiveTree { tree, record_map } = &mut *live_tree;
         let hashed_email = hex::encode(sha2::Sha256::digest(request.user_email.clone()));
-        if !record_map.contains_key(&hashed_email) {
+        let Some(user_pos) = record_map.get(&hashed_email) else {
             tracing::debug!("Not found user");
-            tracing::debug!("map {:?}", record_map);
             return Err(Status::invalid_argument(USER_NOT_FOUND));
-        }
+        };
+        // only the secrets of the user leaf are sent, never the master secret they derive from
+        let (blinding_factor, user_salt) = tree_params.leaf_secrets(user_pos.x_cord());
         let padding_fn = |pos: &NodePosition| {
             new_padding_node_content(
                 &tree_params.master_secret.as_bytes_slice(),
@@ -67,9 +197,20 @@ impl<const N_CURR: usize> SmtBackend for Server<N_CURR> {
             )
         };
 
-        let witness: MerkleWitness<PartialNode, N_CURR> =
-            MerkleWitness::generate_witness(hashed_email, &tree, &record_map, &padding_fn)
+        let mut witness: MerkleWitness<T, N_CURR> =
+            MerkleWitness::generate_oblivious_witness(hashed_email, tree, record_map, &padding_fn)
                 .map_err(|err| Status::aborted(err.to_string()))?;
+        // the proofs only need the nodes of the witness, other requests can use the tree meanwhile
+        drop(live_tree);
+        let witness = tokio::task::spawn_blocking(move || {
+            witness.attach_range_proofs()?;
+            Ok::<_, ErrorKind>(witness.to_partial())
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map_err(|err| Status::internal(err.to_string()))?;
+        let range_proofs = witness.range_proofs.iter().map(Into::into).collect();
+
         let mut node_contents: Vec<NodeContent> = vec![];
         for node in witness.path.0 {
             node_contents.push(node.into());
@@ -90,7 +231,11 @@ impl<const N_CURR: usize> SmtBackend for Server<N_CURR> {
             root,
             user_node,
             for_user: request.user_email,
-            master_salt: tree_params.master_secret.as_vec(),
+            user_salt: user_salt.as_vec(),
+            blinding_factor: encode_scalar(&blinding_factor.to_field())?,
+            version: PROOF_PROTOCOL_VERSION,
+            range_proofs,
+            commitments: T::SCHEME as i32,
         };
         Ok(Response::new(proof))
     }
@@ -100,24 +245,394 @@ impl<const N_CURR: usize> SmtBackend for Server<N_CURR> {
         request: Request<SetRecordRequest>,
     ) -> Result<Response<SetRecordResponse>, Status> {
         let request = request.into_inner();
-        let _balances: Vec<String> = request
+        let new_balances: [u64; N_CURR] = request
             .balances
+            .as_slice()
+            .try_into()
+            .map_err(|_| Status::invalid_argument(format!("expected {} balances", N_CURR)))?;
+        let user_email = request.user_name;
+
+        // held across the write so concurrent updates reach the storage and the tree in the same order
+        let mut live_tree = self.live_tree.lock().await;
+        if let Err(err) = self.db.set_balances(&user_email, &new_balances).await {
+            return Ok(Response::new(SetRecordResponse {
+                msg: err.to_string(),
+            }));
+        }
+
+        let hashed_email = hex::encode(sha2::Sha256::digest(user_email));
+        let updated = match live_tree.record_map.get(&hashed_email).copied() {
+            Some(leaf_pos) => {
+                let record = Record::new(&new_balances, hashed_email);
+                let (_, leaf) =
+                    new_leaf_node::<T, N_CURR>(&self.tree_params, &record, leaf_pos.x_cord());
+                let padding_fn = |pos: &NodePosition| self.tree_params.padding_node_content(pos);
+                match live_tree.tree.update_leaf(leaf_pos, leaf, &padding_fn) {
+                    Ok(written) => {
+                        let written: Vec<(NodePosition, PartialNode)> = written
+                            .iter()
+                            .map(|(pos, node)| (*pos, node.to_partial()))
+                            .collect();
+                        self.db.save_nodes(&written).await.map_err(|err| {
+                            Status::internal(format!(
+                                "the balances were saved but the tree cannot be persisted: {}",
+                                err
+                            ))
+                        })?;
+                        true
+                    }
+                    Err(err) => {
+                        tracing::error!("cannot update the leaf, rebuilding the tree: {}", err);
+                        false
+                    }
+                }
+            }
+            // a new user needs a new position
+            None => false,
+        };
+        if !updated {
+            // the storage is already written so the tree is rebuilt from it to stay in sync
+            *live_tree = Self::build_tree(&*self.db, &self.tree_params, self.epoch)
+                .await
+                .map_err(|err| {
+                    Status::internal(format!(
+                        "the balances were saved but the tree cannot be rebuilt: {}",
+                        err
+                    ))
+                })?;
+        }
+        // both paths change the root the proofs are bound to
+        live_tree
+            .publish_root(&*self.db, &self.tree_params)
+            .await
+            .map_err(|err| {
+                Status::internal(format!(
+                    "the balances were saved but the root cannot be published: {}",
+                    err
+                ))
+            })?;
+        Ok(Response::new(SetRecordResponse {
+            msg: "Saved Succesfully".to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ark_serialize::CanonicalDeserialize;
+    use prost::Message;
+    use sha2::Digest;
+    use tokio_stream::StreamExt;
+    use tonic::Request;
+
+    use super::{Server, PROOF_PROTOCOL_VERSION};
+    use crate::{
+        db::{collect_records, csv::Csv, RecordStream, Storage, StoredRecord},
+        error::{ErrorKind, Result},
+        node_position::{Height, NodePosition},
+        nodes::{partial::PartialNode, vector::VectorNode, ServedNode, TreeNode},
+        pedersen::Pedersen,
+        proofs::{verify_encoded_proof, verify_encoded_vector_proof},
+        smt::{
+            smt_backend_server::SmtBackend, CommitmentScheme, Proof, RequestProof, SetRecordRequest,
+        },
+        store::Store,
+        tree::{RecordMap, TreeParams, SMT},
+        ScalarField,
+    };
+
+    /// the csv records with the updates, the tree and the published roots kept in memory
+    /// so the tests never write the csv
+    #[derive(Debug, Default)]
+    struct Persisting {
+        csv: Csv,
+        updates: std::sync::Mutex<Vec<StoredRecord>>,
+        tree: std::sync::Mutex<Option<(SMT<PartialNode>, RecordMap)>>,
+        published: std::sync::Mutex<Vec<PartialNode>>,
+    }
+
+    #[tonic::async_trait]
+    impl Storage for Persisting {
+        async fn tree_params(&self, password: &[u8]) -> Result<TreeParams> {
+            self.csv.tree_params(password).await
+        }
+
+        async fn records(&self) -> Result<RecordStream<'_>> {
+            let mut records: Vec<Result<StoredRecord>> = self.csv.records().await?.collect().await;
+            for update in self.updates.lock().unwrap().iter() {
+                let existing = records.iter_mut().find(|record| {
+                    matches!(record, Ok(record) if record.hashed_email == update.hashed_email)
+                });
+                match existing {
+                    Some(record) => *record = Ok(update.clone()),
+                    None => records.push(Ok(update.clone())),
+                }
+            }
+            Ok(Box::pin(tokio_stream::iter(records)))
+        }
+
+        async fn set_balances(&self, email: &str, balances: &[u64]) -> Result<()> {
+            self.updates.lock().unwrap().push(StoredRecord {
+                hashed_email: hex::encode(sha2::Sha256::digest(email)),
+                balances: balances.to_vec(),
+            });
+            Ok(())
+        }
+
+        async fn publish_root(
+            &self,
+            root: &PartialNode,
+            _n_records: u64,
+            _tree_params: &TreeParams,
+        ) -> Result<()> {
+            self.published.lock().unwrap().push(root.clone());
+            Ok(())
+        }
+
+        async fn latest_root(&self) -> Result<Option<PartialNode>> {
+            Ok(self.published.lock().unwrap().last().cloned())
+        }
+
+        async fn save_tree(
+            &self,
+            tree: &SMT<PartialNode>,
+            record_map: &RecordMap,
+            _tree_params: &TreeParams,
+        ) -> Result<()> {
+            *self.tree.lock().unwrap() = Some((copy_tree(tree), record_map.clone()));
+            Ok(())
+        }
+
+        async fn load_tree(
+            &self,
+            _tree_params: &TreeParams,
+        ) -> Result<Option<(SMT<PartialNode>, RecordMap)>> {
+            Ok(self
+                .tree
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|(tree, record_map)| (copy_tree(tree), record_map.clone())))
+        }
+    }
+
+    fn copy_tree(tree: &SMT<PartialNode>) -> SMT<PartialNode> {
+        SMT {
+            root: tree.root.clone(),
+            store: Store {
+                map: tree.store.map.clone(),
+            },
+            height: tree.height,
+            store_depth: tree.store_depth,
+        }
+    }
+
+    async fn prove<const N_CURR: usize, T: ServedNode>(
+        server: &Server<N_CURR, T>,
+        email: &str,
+    ) -> Proof {
+        server
+            .generate_proof(Request::new(RequestProof {
+                user_email: email.to_string(),
+                fetch_root: Some(true),
+                fetch_user_node: Some(true),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    #[tokio::test]
+    async fn proofs_never_contain_master_secret() {
+        let csv_db = Csv::default();
+        let emails: Vec<String> = csv::Reader::from_path(&csv_db.file)
+            .unwrap()
+            .records()
+            .map(|record| record.unwrap()[0].to_string())
+            .collect();
+        let tree_params = csv_db.tree_params(&[]).await.unwrap();
+        let master_secret = tree_params.master_secret.as_vec();
+        let records = collect_records::<3>(&csv_db).await.unwrap();
+        let server = Server::<3>::new(Arc::new(csv_db), TreeParams::demo(), 0)
+            .await
+            .unwrap();
+
+        for email in emails {
+            let proof = server
+                .generate_proof(Request::new(RequestProof {
+                    user_email: email.clone(),
+                    fetch_root: Some(true),
+                    fetch_user_node: Some(true),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(proof.version, PROOF_PROTOCOL_VERSION);
+            let bytes = proof.encode_to_vec();
+            assert!(!bytes
+                .windows(master_secret.len())
+                .any(|window| window == master_secret));
+
+            // the blinding factor is the scalar opening the commitment of the user leaf
+            let hashed_email = hex::encode(sha2::Sha256::digest(email));
+            let record = records
+                .iter()
+                .find(|record| record.hashed_email == hashed_email)
+                .unwrap();
+            let blinding_factor =
+                ScalarField::deserialize_uncompressed(proof.blinding_factor.as_slice()).unwrap();
+            let user_node = PartialNode::try_from(proof.user_node.unwrap()).unwrap();
+            assert_eq!(
+                Pedersen::get().commit(record.total_liability().into(), blinding_factor),
+                user_node.commitment
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn proofs_verify_with_range_proofs() {
+        let server = Server::<3>::new(Arc::new(Csv::default()), TreeParams::demo(), 0)
+            .await
+            .unwrap();
+        let email = "a9shgh5zim@gmail.com";
+        let proof = prove(&server, email).await;
+        assert_eq!(proof.range_proofs.len(), proof.path.len() + 1);
+        let root = PartialNode::try_from(proof.root.clone().unwrap()).unwrap();
+        verify_encoded_proof(&proof.encode_to_vec(), &root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restores_the_persisted_tree() {
+        let db = Arc::new(Persisting::default());
+        let email = "a9shgh5zim@gmail.com";
+        let built = Server::<3>::new(db.clone(), TreeParams::demo(), 0)
+            .await
+            .unwrap();
+        let built_root = prove(&built, email).await.root.unwrap();
+
+        // a rebuild with another epoch would place the leaves elsewhere
+        let restored = Server::<3>::new(db.clone(), TreeParams::demo(), 1)
+            .await
+            .unwrap();
+        let proof = prove(&restored, email).await;
+        assert_eq!(proof.root.clone().unwrap(), built_root);
+        let root = PartialNode::try_from(built_root).unwrap();
+        verify_encoded_proof(&proof.encode_to_vec(), &root).unwrap();
+
+        // a persisted root the records do not open to is replaced by a rebuild
+        let stale = PartialNode::merge(&root, &root, &NodePosition::new(0, Height::new(1)));
+        db.tree.lock().unwrap().as_mut().unwrap().0.root = stale.clone();
+        let rebuilt = Server::<3>::new(db, TreeParams::demo(), 0).await.unwrap();
+        let rebuilt_root = prove(&rebuilt, email).await.root.unwrap();
+        assert_eq!(PartialNode::try_from(rebuilt_root).unwrap(), root);
+    }
+
+    #[tokio::test]
+    async fn per_asset_proofs_open_to_the_balances() {
+        let db = Arc::new(Persisting::default());
+        let email = "a9shgh5zim@gmail.com";
+        let hashed_email = hex::encode(sha2::Sha256::digest(email));
+        let records = collect_records::<3>(&db.csv).await.unwrap();
+        let balances = *records
             .iter()
-            .map(|balance| balance.to_string())
+            .find(|record| record.hashed_email == hashed_email)
+            .unwrap()
+            .balances();
+        let server = Server::<3, VectorNode>::new(db.clone(), TreeParams::demo(), 0)
+            .await
+            .unwrap();
+        let proof = prove(&server, email).await;
+        assert_eq!(proof.commitments, CommitmentScheme::PerAsset as i32);
+        assert!(proof.range_proofs.is_empty());
+        let root = PartialNode::try_from(proof.root.clone().unwrap()).unwrap();
+        let bytes = proof.encode_to_vec();
+        verify_encoded_vector_proof(&bytes, &root, &balances).unwrap();
+
+        let mut wrong_balances = balances;
+        wrong_balances[0] += 1;
+        assert!(matches!(
+            verify_encoded_vector_proof(&bytes, &root, &wrong_balances),
+            Err(ErrorKind::ProofOpeningMismatch)
+        ));
+        // the total liability verifier refuses per asset proofs instead of skipping range proofs
+        assert!(matches!(
+            verify_encoded_proof(&bytes, &root),
+            Err(ErrorKind::ProofSchemeMismatch { .. })
+        ));
+
+        // the persisted per asset tree is restored rather than rebuilt with the new epoch
+        let restored = Server::<3, VectorNode>::new(db, TreeParams::demo(), 1)
+            .await
+            .unwrap();
+        let restored_proof = prove(&restored, email).await;
+        assert_eq!(restored_proof.root, proof.root);
+        verify_encoded_vector_proof(&restored_proof.encode_to_vec(), &root, &balances).unwrap();
+    }
+
+    /// the root published after an in place update is the one the served proofs are bound to
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_publishes_updated_roots() {
+        use crate::{db::sqlite::Sqlite, keyfile::generate_tree_params, record::Record};
+
+        let db = Arc::new(Sqlite::open_in_memory().unwrap());
+        let records: Vec<Record<3>> = (1..=5u64)
+            .map(|i| Record::new(&[i, 2 * i, 3 * i], format!("user_{}", i)))
             .collect();
-        let mut balances: [String; N_CURR] = [(); N_CURR].map(|_| String::default());
-        for i in 0..N_CURR {
-            balances[i] = _balances[i].clone();
+        db.set_records(&records).unwrap();
+        let email = "alice@example.com";
+        db.set_balances(email, &[10, 0, 5]).await.unwrap();
+        let server = Server::<3>::new(db.clone(), generate_tree_params(), 0)
+            .await
+            .unwrap();
+        let built_root = db.latest_root().await.unwrap().unwrap();
+
+        let response = server
+            .set_user_data(Request::new(SetRecordRequest {
+                balances: vec![11, 1, 5],
+                user_name: email.to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.msg, "Saved Succesfully");
+        let latest_root = db.latest_root().await.unwrap().unwrap();
+        assert_ne!(latest_root, built_root);
+        let proof = prove(&server, email).await;
+        verify_encoded_proof(&proof.encode_to_vec(), &latest_root).unwrap();
+    }
+
+    /// an in place update and a new user both publish the root of the proofs served next
+    #[tokio::test]
+    async fn updates_publish_the_served_root() {
+        let db = Arc::new(Persisting::default());
+        let server = Server::<3>::new(db.clone(), TreeParams::demo(), 0)
+            .await
+            .unwrap();
+        assert_eq!(db.published.lock().unwrap().len(), 1);
+
+        let existing = "a9shgh5zim@gmail.com";
+        let new_user = "new_user@example.com";
+        for (published, email) in [(2, existing), (3, new_user)] {
+            let response = server
+                .set_user_data(Request::new(SetRecordRequest {
+                    balances: vec![1, 2, 3],
+                    user_name: email.to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.msg, "Saved Succesfully");
+            assert_eq!(db.published.lock().unwrap().len(), published);
+            let latest_root = db.latest_root().await.unwrap().unwrap();
+            let proof = prove(&server, email).await;
+            assert_eq!(
+                PartialNode::try_from(proof.root.clone().unwrap()).unwrap(),
+                latest_root
+            );
+            verify_encoded_proof(&proof.encode_to_vec(), &latest_root).unwrap();
         }
-        let user_email = request.user_name;
-        let mut db = self
-            .db
-            .lock()
-            .map_err(|err| Status::aborted(err.to_string()))?;
-        let msg = match db.set_record_new_balances(&user_email, &balances) {
-            Ok(_) => "Saved Succesfully".to_string(),
-            Err(err) => err.to_string(),
-        };
-        return Ok(Response::new(SetRecordResponse { msg }));
     }
 }
@@ -1,12 +1,35 @@
-use crate::{node_position::NodePosition, secret::Secret, BaseField, CurvePoint};
-use ark_serialize::CanonicalSerialize;
-use mina_hasher::{Hashable, ROInput};
+use crate::{node_position::NodePosition, record::Record, secret::Secret, BaseField, CurvePoint};
+use ark_ec::AffineRepr;
+use ark_ff::{PrimeField, Zero};
+use mina_hasher::{create_legacy, Hashable, Hasher, ROInput};
 use mina_poseidon::{
     constants::PlonkSpongeConstantsKimchi,
     pasta::fp_kimchi,
     poseidon::{ArithmeticSponge as Poseidon, Sponge as _},
 };
 
+/// Version of the hashing scheme, bumped whenever the hash of a node changes
+///
+/// version 1
+/// - leaf `H_leaf(user_id | user_salt)`
+/// - pad `H_pad(x | height | pad_secret)` with the position of the pad
+/// - internal node `poseidon(NODE_TAG | x + 2^64 * height | left.com | right.com | left.hash | right.hash)`
+///   with the position of the node and the commitments encoded by `commitment_to_fields`
+///
+/// `H_leaf` and `H_pad` are the legacy mina hasher with the domain strings of `HashDomain`,
+/// `NODE_TAG` is the little-endian integer of the bytes of `NODE_DOMAIN`
+pub const HASH_SCHEME_VERSION: u32 = 1;
+
+/// Domain of the internal node hashes
+pub const NODE_DOMAIN: &[u8] = b"oram-smst-node-v1";
+
+/// The domains of the hashes of the nodes built from bytes
+#[derive(Clone, Copy, Debug)]
+pub enum HashDomain {
+    Leaf,
+    Pad,
+}
+
 #[derive(Clone)]
 pub enum Hashables {
     Bytes(Vec<u8>),
@@ -28,15 +51,14 @@ impl Hashables {
 }
 
 impl Hashable for Hashables {
-    type D = ();
+    type D = HashDomain;
     fn to_roinput(&self) -> ROInput {
         match &self {
             Self::Bytes(bytes) => ROInput::new().append_bytes(bytes),
             Self::Secret(sec) => ROInput::new().append_bytes(sec.as_bytes_slice()),
             Self::Commitment(point) => {
-                let mut compressed_bytes = Vec::new();
-                point.serialize_compressed(&mut compressed_bytes).unwrap();
-                ROInput::new().append_bytes(&compressed_bytes)
+                let [x, y] = commitment_to_fields(point);
+                ROInput::new().append_field(x).append_field(y)
             }
             Self::Hash(h) => ROInput::new().append_field(*h),
             Self::Position(node_pos) => {
@@ -48,14 +70,165 @@ impl Hashable for Hashables {
         }
     }
 
-    fn domain_string(_domain_param: Self::D) -> Option<String> {
-        "Bytes hashed".to_string().into()
+    /// mina pads the domain strings upto 20 bytes
+    fn domain_string(domain: Self::D) -> Option<String> {
+        match domain {
+            HashDomain::Leaf => "oram-smst-leaf-v1",
+            HashDomain::Pad => "oram-smst-pad-v1",
+        }
+        .to_string()
+        .into()
     }
 }
 
+/// canonical field encoding of a commitment, `(x, y)` for an affine point and `(0, 0)` for the
+/// point at infinity, `(0, 0)` is not on `y^2 = x^3 + 5` so it cannot collide with a point
+pub fn commitment_to_fields(commitment: &CurvePoint) -> [BaseField; 2] {
+    commitment
+        .xy()
+        .map(|(x, y)| [*x, *y])
+        .unwrap_or([BaseField::zero(); 2])
+}
+
+/// hash of a leaf `H_leaf(user_id | user_salt)`
+pub fn leaf_hash<const N_CURR: usize>(record: &Record<N_CURR>, user_salt: Secret) -> BaseField {
+    let mut hasher = create_legacy::<Hashables>(HashDomain::Leaf);
+    hasher.update(&record.to_hashable());
+    hasher.update(&Hashables::Secret(user_salt));
+    hasher.digest()
+}
+
+/// hash of a pad `H_pad(x | height | pad_secret)`
+pub fn pad_hash(position: NodePosition, pad_secret: Secret) -> BaseField {
+    let mut hasher = create_legacy::<Hashables>(HashDomain::Pad);
+    hasher.update(&Hashables::Position(position));
+    hasher.update(&Hashables::Secret(pad_secret));
+    hasher.digest()
+}
+
+/// hash of the internal node at `position`
+pub fn merge_hash(
+    position: &NodePosition,
+    left_commitment: &CurvePoint,
+    left_hash: BaseField,
+    right_commitment: &CurvePoint,
+    right_hash: BaseField,
+) -> BaseField {
+    let [left_x, left_y] = commitment_to_fields(left_commitment);
+    let [right_x, right_y] = commitment_to_fields(right_commitment);
+    poseidon_hash(&[
+        BaseField::from_le_bytes_mod_order(NODE_DOMAIN),
+        position_to_field(position),
+        left_x,
+        left_y,
+        right_x,
+        right_y,
+        left_hash,
+        right_hash,
+    ])
+}
+
+/// `x + 2^64 * height`
+fn position_to_field(position: &NodePosition) -> BaseField {
+    let shift = BaseField::from(u64::MAX) + BaseField::from(1u64);
+    BaseField::from(position.x_cord()) + shift * BaseField::from(position.1.as_u64())
+}
+
 pub fn poseidon_hash(field_elems: &[BaseField]) -> BaseField {
     let mut hash =
         Poseidon::<BaseField, PlonkSpongeConstantsKimchi>::new(fp_kimchi::static_params());
     hash.absorb(field_elems);
     hash.squeeze()
 }
+
+#[cfg(test)]
+mod tests {
+    use ark_ec::{AffineRepr, CurveGroup};
+    use ark_serialize::CanonicalSerialize;
+
+    use super::{commitment_to_fields, leaf_hash, merge_hash, pad_hash};
+    use crate::{
+        node_position::{Height, NodePosition},
+        nodes::{partial::PartialNode, TreeNode},
+        pedersen::Pedersen,
+        record::Record,
+        secret::Secret,
+        BaseField, CurvePoint, ScalarField,
+    };
+
+    /// the inputs of the shared test vectors, leaf, pad and internal node hashes of the same data
+    fn vectors() -> [(&'static str, BaseField); 4] {
+        let record = Record::new(&[1, 2, 3], String::from("user"));
+        let leaf = leaf_hash(&record, Secret::from(1u32));
+        let pad = pad_hash(NodePosition::new(1, Height::new(0)), Secret::from(1u32));
+        let commitment = Pedersen::get().commit(ScalarField::from(6u64), ScalarField::from(1u64));
+        let parent = |position| merge_hash(&position, &commitment, leaf, &commitment, pad);
+        [
+            ("leaf", leaf),
+            ("pad", pad),
+            ("node_0_1", parent(NodePosition::new(0, Height::new(1)))),
+            ("node_1_1", parent(NodePosition::new(1, Height::new(1)))),
+        ]
+    }
+
+    #[test]
+    fn node_kinds_and_positions_are_separated() {
+        let hashes: Vec<BaseField> = vectors().iter().map(|(_, hash)| *hash).collect();
+        for (i, a) in hashes.iter().enumerate() {
+            for b in &hashes[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    /// the pinned test vectors of version 1 of the hashing scheme, shared with the circuits,
+    /// as the hex of the uncompressed little endian bytes of each hash
+    const TEST_VECTORS_V1: [(&str, &str); 4] = [
+        ("leaf", ""),
+        ("pad", ""),
+        ("node_0_1", ""),
+        ("node_1_1", ""),
+    ];
+
+    fn to_hex(hash: BaseField) -> String {
+        let mut bytes = vec![];
+        hash.serialize_uncompressed(&mut bytes).unwrap();
+        hex::encode(bytes)
+    }
+
+    #[test]
+    fn test_vectors_are_pinned() {
+        for ((name, hash), (pinned_name, pinned_hex)) in vectors().into_iter().zip(TEST_VECTORS_V1)
+        {
+            assert_eq!(name, pinned_name);
+            let hex = to_hex(hash);
+            assert_eq!(
+                hex, pinned_hex,
+                "the {} hash is (\"{}\", \"{}\")",
+                name, name, hex
+            );
+        }
+    }
+
+    #[test]
+    fn merges_cancelling_commitments() {
+        let commitment = Pedersen::get().commit(ScalarField::from(0u64), ScalarField::from(5u64));
+        let cancelling = (-commitment.into_group()).into_affine();
+        let left = PartialNode::new(commitment, BaseField::from(1u64));
+        let right = PartialNode::new(cancelling, BaseField::from(2u64));
+
+        let parent = PartialNode::merge(&left, &right, &NodePosition::new(0, Height::new(1)));
+        assert!(parent.commitment.is_zero());
+        assert_eq!(
+            commitment_to_fields(&parent.commitment),
+            [BaseField::from(0u64); 2]
+        );
+        let grand_parent =
+            PartialNode::merge(&parent, &parent, &NodePosition::new(0, Height::new(2)));
+        assert!(grand_parent.commitment.is_zero());
+        assert_ne!(grand_parent.hash(), parent.hash());
+        assert!(
+            !CurvePoint::new_unchecked(BaseField::from(0u64), BaseField::from(0u64)).is_on_curve()
+        );
+    }
+}
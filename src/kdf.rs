@@ -1,14 +1,72 @@
+use ark_ff::PrimeField;
 use sha2::Sha256;
 
+use crate::ScalarField;
+
+/// Domain of the hash to scalar derivation
+pub const HASH_TO_SCALAR_DOMAIN: &[u8] = b"oram-smst hash to scalar";
+
 /// This is the key derivation function for the protocol
 /// the `w = kdf(master_salt , id)` `s = kdf(w , salt_s)` `b = kdf(b, salt_b)`
 pub fn kdf(salt: Option<&[u8]>, id: Option<&[u8]>, ikm: &[u8]) -> [u8; 32] {
+    expand(salt, id, ikm)
+}
+
+/// the kdf with a 512-bit output, used for wide reductions
+pub fn kdf_wide(salt: Option<&[u8]>, id: Option<&[u8]>, ikm: &[u8]) -> [u8; 64] {
+    expand(salt, id, ikm)
+}
+
+/// maps `ikm` to a uniformly distributed scalar
+/// `HKDF-SHA256(salt = none, ikm, info = "oram-smst hash to scalar", L = 64)` read as a
+/// little-endian integer and reduced modulo the pallas scalar field order, the bias of the
+/// reduction is below `2^-254`
+pub fn hash_to_scalar(ikm: &[u8]) -> ScalarField {
+    ScalarField::from_le_bytes_mod_order(&kdf_wide(None, Some(HASH_TO_SCALAR_DOMAIN), ikm))
+}
+
+fn expand<const L: usize>(salt: Option<&[u8]>, id: Option<&[u8]>, ikm: &[u8]) -> [u8; L] {
     if salt.is_none() && id.is_none() {
         panic!("salt and byte both not provided");
     }
     let hk = hkdf::Hkdf::<Sha256>::new(salt, ikm);
-    let mut okm = [0u8; 32];
+    let mut okm = [0u8; L];
     hk.expand(id.unwrap_or_default(), &mut okm)
-        .expect("42 is a valid length for Sha256 to output");
+        .expect("64 is a valid length for Sha256 to output");
     okm
 }
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigUint;
+
+    use super::hash_to_scalar;
+    use crate::{secret::Secret, ScalarField};
+
+    /// vectors computed independently with python's `hmac` and `hashlib`
+    #[test]
+    fn hash_to_scalar_vectors() {
+        let (one, two) = (Secret::from(1u32), Secret::from(2u32));
+        let vectors: [(&[u8], &str); 3] = [
+            (
+                one.as_bytes_slice(),
+                "5195111585181951346897733600865820716098117881271179892833805415776767173008",
+            ),
+            (
+                two.as_bytes_slice(),
+                "11669473510555742728830936690518572765280716316277473898377759567539279830870",
+            ),
+            (
+                &[
+                    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21,
+                    22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+                ],
+                "10105425108599580085018223816181088532901871593556719487889489411833742349992",
+            ),
+        ];
+        for (ikm, expected) in vectors {
+            let expected = BigUint::parse_bytes(expected.as_bytes(), 10).unwrap();
+            assert_eq!(hash_to_scalar(ikm), ScalarField::from(expected));
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::{fs::OpenOptions, io::Write, path::Path};
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{
+    error::{ErrorKind, Result},
+    salt::Salt,
+    secret::Secret,
+    tree::TreeParams,
+};
+
+/// Version of the keyfile format, also bound to the ciphertext as associated data
+pub const KEYFILE_VERSION: u32 = 1;
+
+/// Environment variable the keyfile password is read from
+pub const PASSWORD_ENV: &str = "ORAM_SMST_KEYFILE_PASSWORD";
+
+const ASSOCIATED_DATA: &[u8] = b"oram-smst keyfile v1";
+
+/// The tree params encrypted with a key derived from a password with argon2id
+#[derive(Serialize, Deserialize)]
+struct Keyfile {
+    version: u32,
+    #[serde(with = "hex")]
+    kdf_salt: [u8; 16],
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    #[serde(with = "hex")]
+    nonce: [u8; 12],
+    /// `master_secret | salt_s | salt_b` with the authentication tag
+    #[serde(with = "hex")]
+    ciphertext: Vec<u8>,
+}
+
+/// fresh random tree params for a new deployment
+pub fn generate_tree_params() -> TreeParams {
+    let mut rng = rand::rng();
+    TreeParams {
+        master_secret: Secret::from(rng.random::<[u8; 32]>()),
+        salt_s: Salt::from(rng.random::<[u8; 32]>()),
+        salt_b: Salt::from(rng.random::<[u8; 32]>()),
+    }
+}
+
+/// encrypts the tree params with `password` and writes them to `path`
+/// the file is only readable by its owner
+pub fn save<P: AsRef<Path>>(tree_params: &TreeParams, password: &[u8], path: P) -> Result<()> {
+    let json = encrypt(tree_params, password)?;
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(path)
+        .map_err(|err| ErrorKind::Keyfile(err.to_string()))?;
+    // the mode is only applied to new files
+    #[cfg(unix)]
+    file.set_permissions(Permissions::from_mode(0o600))
+        .map_err(|err| ErrorKind::Keyfile(err.to_string()))?;
+    file.write_all(&json)
+        .map_err(|err| ErrorKind::Keyfile(err.to_string()))?;
+    Ok(())
+}
+
+/// reads and decrypts a keyfile written by `save`
+pub fn load<P: AsRef<Path>>(path: P, password: &[u8]) -> Result<TreeParams> {
+    let json = std::fs::read(path).map_err(|err| ErrorKind::Keyfile(err.to_string()))?;
+    decrypt(&json, password)
+}
+
+/// the contents of a keyfile for the tree params, used to store them in a database
+pub fn encrypt(tree_params: &TreeParams, password: &[u8]) -> Result<Vec<u8>> {
+    let mut rng = rand::rng();
+    let params = Params::default();
+    let mut keyfile = Keyfile {
+        version: KEYFILE_VERSION,
+        kdf_salt: rng.random(),
+        m_cost: params.m_cost(),
+        t_cost: params.t_cost(),
+        p_cost: params.p_cost(),
+        nonce: rng.random(),
+        ciphertext: vec![],
+    };
+
+    let mut plaintext = Zeroizing::new(Vec::with_capacity(96));
+    plaintext.extend_from_slice(tree_params.master_secret.as_bytes_slice());
+    plaintext.extend_from_slice(&tree_params.salt_s.as_bytes());
+    plaintext.extend_from_slice(&tree_params.salt_b.as_bytes());
+    keyfile.ciphertext = cipher(&keyfile, password)?
+        .encrypt(
+            Nonce::from_slice(&keyfile.nonce),
+            Payload {
+                msg: &plaintext,
+                aad: ASSOCIATED_DATA,
+            },
+        )
+        .map_err(|_| ErrorKind::Keyfile("encryption failed".to_string()))?;
+
+    serde_json::to_vec(&keyfile).map_err(|err| ErrorKind::Keyfile(err.to_string()))
+}
+
+/// decrypts the contents of a keyfile written by `encrypt`
+pub fn decrypt(json: &[u8], password: &[u8]) -> Result<TreeParams> {
+    let keyfile: Keyfile =
+        serde_json::from_slice(json).map_err(|err| ErrorKind::Keyfile(err.to_string()))?;
+    if keyfile.version != KEYFILE_VERSION {
+        return Err(ErrorKind::Keyfile(format!(
+            "unsupported version {}",
+            keyfile.version
+        )));
+    }
+
+    let plaintext = Zeroizing::new(
+        cipher(&keyfile, password)?
+            .decrypt(
+                Nonce::from_slice(&keyfile.nonce),
+                Payload {
+                    msg: &keyfile.ciphertext,
+                    aad: ASSOCIATED_DATA,
+                },
+            )
+            .map_err(|_| ErrorKind::KeyfileDecryption)?,
+    );
+    if plaintext.len() != 96 {
+        return Err(ErrorKind::Keyfile("wrong plaintext length".to_string()));
+    }
+    let part = |i: usize| -> [u8; 32] { plaintext[i * 32..(i + 1) * 32].try_into().unwrap() };
+    Ok(TreeParams {
+        master_secret: Secret::from(part(0)),
+        salt_s: Salt::from(part(1)),
+        salt_b: Salt::from(part(2)),
+    })
+}
+
+/// reads the keyfile password from `PASSWORD_ENV`
+pub fn password_from_env() -> Result<Zeroizing<Vec<u8>>> {
+    std::env::var(PASSWORD_ENV)
+        .map(|password| Zeroizing::new(password.into_bytes()))
+        .map_err(|_| ErrorKind::Keyfile(format!("{} is not set", PASSWORD_ENV)))
+}
+
+fn cipher(keyfile: &Keyfile, password: &[u8]) -> Result<ChaCha20Poly1305> {
+    let params = Params::new(keyfile.m_cost, keyfile.t_cost, keyfile.p_cost, Some(32))
+        .map_err(|err| ErrorKind::Keyfile(err.to_string()))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password, &keyfile.kdf_salt, &mut *key)
+        .map_err(|err| ErrorKind::Keyfile(err.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&*key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt, encrypt, generate_tree_params, load, save};
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn keyfile_round_trip() {
+        let tree_params = generate_tree_params();
+        assert!(!tree_params.is_demo());
+        let path = std::env::temp_dir().join("oram_smst_keyfile_round_trip.json");
+        save(&tree_params, b"correct horse", &path).unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let loaded = load(&path, b"correct horse").unwrap();
+        assert_eq!(loaded.fingerprint(), tree_params.fingerprint());
+        assert!(matches!(
+            load(&path, b"battery staple"),
+            Err(ErrorKind::KeyfileDecryption)
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn encrypted_params_round_trip() {
+        let tree_params = generate_tree_params();
+        let json = encrypt(&tree_params, b"correct horse").unwrap();
+        let plain = hex::encode(tree_params.master_secret.as_bytes_slice());
+        assert!(!String::from_utf8_lossy(&json).contains(&plain));
+        assert_eq!(
+            decrypt(&json, b"correct horse").unwrap().fingerprint(),
+            tree_params.fingerprint()
+        );
+        assert!(decrypt(&json[1..], b"correct horse").is_err());
+    }
+}
@@ -1,20 +1,24 @@
+pub mod config;
 pub mod db;
-mod error;
+pub mod error;
 pub mod grpc;
 pub mod hasher;
 pub mod kdf;
+pub mod keyfile;
 pub mod node_position;
 pub mod nodes;
 pub mod path_oram;
 pub mod pedersen;
 pub mod proofs;
 pub mod range_check;
+pub mod range_proof;
 pub mod record;
 pub mod salt;
 pub mod secret;
 pub mod serialize;
 pub mod siblings;
 pub mod smt;
+pub mod snapshot;
 pub mod store;
 pub mod tier_ratios;
 pub mod tree;
@@ -1,24 +1,98 @@
+use clap::Parser;
+use cli::{Cli, Command};
+use oram_smst::config::{Commitments, Config, MAX_ASSETS};
 use oram_smst::grpc::Server as SMTServer;
+use oram_smst::nodes::{node::Node, vector::VectorNode, ServedNode};
 use oram_smst::smt::smt_backend_server::SmtBackendServer;
 use std::error;
 use tonic::transport::Server;
 
+mod cli;
+
 mod smt_proto {
     include!("smt.rs");
     pub(crate) const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("smt");
 }
 
+/// calls `$f::<N>` for the number of assets `$n`, `N_CURR` is a const generic so it is matched
+/// to a concrete instantiation, one arm for each of `1..=MAX_ASSETS`
+macro_rules! with_n_assets {
+    ($n:expr, $($f:ident)::+($($arg:expr),*)) => {
+        match $n {
+            1 => $($f)::+::<1>($($arg),*).await,
+            2 => $($f)::+::<2>($($arg),*).await,
+            3 => $($f)::+::<3>($($arg),*).await,
+            4 => $($f)::+::<4>($($arg),*).await,
+            5 => $($f)::+::<5>($($arg),*).await,
+            6 => $($f)::+::<6>($($arg),*).await,
+            7 => $($f)::+::<7>($($arg),*).await,
+            8 => $($f)::+::<8>($($arg),*).await,
+            n => Err(format!("the number of assets must be between 1 and {}, got {}", MAX_ASSETS, n).into()),
+        }
+    };
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn error::Error>> {
-    let addr = "[::1]:50051".parse()?;
-    let server = SMTServer::<3>::default();
+    match Cli::parse().command {
+        Command::Build {
+            input,
+            out,
+            height,
+            n_assets,
+            epoch,
+            keyfile,
+            dev,
+        } => with_n_assets!(
+            n_assets,
+            cli::build(input, out, height, epoch, keyfile, dev)
+        ),
+        Command::Prove {
+            user,
+            tree,
+            keyfile,
+            dev,
+            out,
+        } => cli::prove(user, tree, keyfile, dev, out),
+        Command::Verify {
+            proof,
+            root,
+            config,
+        } => cli::verify(proof, root, config).await,
+        Command::Keygen { out, config } => cli::keygen(out, config).await,
+        Command::Serve { config, addr, dev } => {
+            let mut config = Config::load(config.as_deref())?;
+            if let Some(addr) = addr {
+                config.addr = addr;
+            }
+            config.dev |= dev;
+            with_n_assets!(config.n_assets, serve(config))
+        }
+    }
+}
+
+async fn serve<const N_CURR: usize>(config: Config) -> Result<(), Box<dyn error::Error>> {
+    tracing_subscriber::fmt()
+        .with_max_level(config.log_level()?)
+        .init();
+    match config.commitments {
+        Commitments::Total => serve_tree::<N_CURR, Node>(config).await,
+        Commitments::PerAsset => serve_tree::<N_CURR, VectorNode>(config).await,
+    }
+}
+
+/// serves the tree whose leaves are `T`
+async fn serve_tree<const N_CURR: usize, T: ServedNode>(
+    config: Config,
+) -> Result<(), Box<dyn error::Error>> {
+    let db = config.db().await?;
+    let tree_params = config.tree_params(&*db).await?;
+    let server = SMTServer::<N_CURR, T>::new(db, tree_params, config.epoch).await?;
+    let addr = config.addr;
     let reflection_service = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(smt_proto::FILE_DESCRIPTOR_SET)
         .build_v1()
         .unwrap();
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .init();
     tracing::info!(message = "Starting server.", %addr);
     Server::builder()
         .trace_fn(|_| tracing::info_span!("SMTBackend server"))
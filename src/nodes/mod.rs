@@ -1,9 +1,15 @@
+use std::fmt::Debug;
+
+use serde::Serialize;
+
 use crate::{
-    node_position::NodePosition, record::Record, secret::Secret, tree_builder::PaddingNodeContent,
+    error::Result, node_position::NodePosition, range_proof::RangeProof, record::Record,
+    secret::Secret, smt::CommitmentScheme, tree_builder::PaddingNodeContent,
 };
 
 pub mod node;
 pub mod partial;
+pub mod vector;
 
 // Common trait for both partial and full node
 
@@ -14,7 +20,34 @@ pub trait TreeNode {
         user_salt: Secret,
     ) -> Self;
 
+    /// creates the leaves for `(blinding_factor, record, user_salt)` at once
+    /// implementations can override it to batch the commitments
+    fn new_leaves<const N_CURR: usize>(leaves: Vec<(Secret, &Record<N_CURR>, Secret)>) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        leaves
+            .into_iter()
+            .map(|(blinding_factor, record, user_salt)| {
+                Self::new_leaf(blinding_factor, record, user_salt)
+            })
+            .collect()
+    }
+
     fn new_pad(padding_node_content: PaddingNodeContent, position: NodePosition) -> Self;
 
-    fn merge(left_child: &Self, right_child: &Self) -> Self;
+    /// the parent at `position` of the two children
+    fn merge(left_child: &Self, right_child: &Self, position: &NodePosition) -> Self;
+}
+
+/// A node the server keeps in its live tree, only its partial node is persisted or sent
+pub trait ServedNode: TreeNode + Clone + Debug + Serialize + Send + Sync + 'static {
+    /// what the commitments of the nodes open to, announced in every proof
+    const SCHEME: CommitmentScheme;
+
+    /// strips everything but the commitment and the hash
+    fn to_partial(&self) -> partial::PartialNode;
+
+    /// the range proofs of the commitments of `nodes`, empty when they cannot be proven
+    fn range_proofs(nodes: &[&Self]) -> Result<Vec<RangeProof>>;
 }
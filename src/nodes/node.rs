@@ -1,24 +1,23 @@
-use super::{partial::PartialNode, TreeNode};
+use super::{partial::PartialNode, ServedNode, TreeNode};
 use crate::{
-    error::ErrorKind,
-    hasher::{poseidon_hash, Hashables},
+    error::{ErrorKind, Result},
+    hasher::{leaf_hash, merge_hash, pad_hash},
     node_position::NodePosition,
     pedersen::Pedersen,
+    range_proof::{Committed, Opened, RangeProof},
     record::Record,
     secret::Secret,
-    smt::NodeContent,
+    smt::{CommitmentScheme, NodeContent},
     tree_builder::PaddingNodeContent,
     BaseField, CurvePoint, ScalarField,
 };
-use ark_ec::AffineRepr;
 use ark_serialize::CanonicalSerialize;
-use mina_hasher::{create_legacy, Hasher};
 use num_bigint::BigUint;
 use serde::{de::Error, Deserialize, Serialize};
 use serde_with::serde_as;
 /// A Node for the SMT
 #[serde_as]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Node {
     liability: BigUint,
     /// we are using `SerdeAsUnchecked` so we do not need to do uncompression logic in ts
@@ -52,6 +51,38 @@ impl Node {
     }
 }
 
+impl ServedNode for Node {
+    const SCHEME: CommitmentScheme = CommitmentScheme::Total;
+
+    fn to_partial(&self) -> PartialNode {
+        Node::to_partial(self)
+    }
+
+    fn range_proofs(nodes: &[&Self]) -> Result<Vec<RangeProof>> {
+        let mut rng = ark_std::rand::thread_rng();
+        nodes
+            .iter()
+            .map(|node| RangeProof::for_node(*node, &mut rng))
+            .collect()
+    }
+}
+
+impl Committed for Node {
+    fn commitment(&self) -> CurvePoint {
+        self.commitment
+    }
+}
+
+impl Opened for Node {
+    fn liability(&self) -> &BigUint {
+        &self.liability
+    }
+
+    fn blinding_factor(&self) -> ScalarField {
+        self.blinding_factor
+    }
+}
+
 impl TreeNode for Node {
     /// returns a new leaf given a user's db record
     /// the blinding factor secret is calculate as `KDF(wu,salt_b)` where wu = `KDF(master_secret , id_u)`
@@ -64,15 +95,9 @@ impl TreeNode for Node {
         let blinding_factor = blinding_factor_secret.to_field();
         let total_liability = record.total_liability();
 
-        let commitment =
-            Pedersen::default().commit(total_liability.clone().into(), blinding_factor);
+        let commitment = Pedersen::get().commit(total_liability.clone().into(), blinding_factor);
 
-        // compute the hash `H("leaf" | user_id | user_salt)`
-        let mut hasher = create_legacy::<Hashables>(());
-        hasher.update(&Hashables::from_slice("leaf".as_bytes()));
-        hasher.update(&record.to_hashable());
-        hasher.update(&Hashables::Secret(user_secret));
-        let hash = hasher.digest();
+        let hash = leaf_hash(record, user_secret);
         Self {
             liability: total_liability,
             blinding_factor,
@@ -81,6 +106,37 @@ impl TreeNode for Node {
         }
     }
 
+    /// the leaves with their commitments computed in one batch
+    fn new_leaves<const N_CURR: usize>(
+        leaves: Vec<(Secret, &Record<N_CURR>, Secret)>,
+    ) -> Vec<Self> {
+        let openings: Vec<(BigUint, ScalarField)> = leaves
+            .iter()
+            .map(|(blinding_factor, record, _)| {
+                (record.total_liability(), blinding_factor.to_field())
+            })
+            .collect();
+        let commitments = Pedersen::get().commit_batch(
+            &openings
+                .iter()
+                .map(|(liability, blinding_factor)| (liability.clone().into(), *blinding_factor))
+                .collect::<Vec<_>>(),
+        );
+        leaves
+            .into_iter()
+            .zip(openings)
+            .zip(commitments)
+            .map(
+                |(((_, record, user_secret), (liability, blinding_factor)), commitment)| Self {
+                    liability,
+                    blinding_factor,
+                    commitment,
+                    hash: leaf_hash(record, user_secret),
+                },
+            )
+            .collect()
+    }
+
     /// create a new pad node
     /// level is the height at which the pad is required
     /// level_offset is the offset from the left to the point we are inserting the node assuming that
@@ -90,13 +146,9 @@ impl TreeNode for Node {
 
         let blinding_factor = padding_node_content.bliding_factor().to_field();
 
-        let commitment = Pedersen::default().commit(liability.into(), blinding_factor);
+        let commitment = Pedersen::get().commit(liability.into(), blinding_factor);
 
-        let mut hasher = create_legacy::<Hashables>(());
-        hasher.update(&Hashables::from_slice("pad".as_bytes()));
-        hasher.update(&Hashables::Position(position));
-        hasher.update(&Hashables::Secret(padding_node_content.user_secret()));
-        let hash = hasher.digest();
+        let hash = pad_hash(position, padding_node_content.user_secret());
         Self {
             liability: liability.into(),
             blinding_factor,
@@ -105,22 +157,19 @@ impl TreeNode for Node {
         }
     }
 
-    fn merge(left_child: &Self, right_child: &Self) -> Self {
+    fn merge(left_child: &Self, right_child: &Self, position: &NodePosition) -> Self {
         //TODO: Think of something better to remote this clone
         let liability = left_child.liability.clone() + right_child.liability.clone();
         let blinding_factor: ScalarField = left_child.blinding_factor + right_child.blinding_factor;
         let commitment = (left_child.commitment + right_child.commitment).into();
 
-        // H = H(left.com | right.com | left.hash | right.hash )
-        let hash_inputs = [
-            *left_child.commitment.x().unwrap(),
-            *left_child.commitment.y().unwrap(),
-            *right_child.commitment.x().unwrap(),
-            *right_child.commitment.y().unwrap(),
+        let hash = merge_hash(
+            position,
+            &left_child.commitment,
             left_child.hash,
+            &right_child.commitment,
             right_child.hash,
-        ];
-        let hash = poseidon_hash(&hash_inputs);
+        );
         Self {
             liability,
             blinding_factor,
@@ -165,8 +214,8 @@ mod tests {
         let user_secret = Secret::from(1u32);
         let leaf = Node::new_leaf(blinding_factor.clone(), &record, user_secret.clone());
         let pad_content = PaddingNodeContent::new(blinding_factor, user_secret);
-        let pad = Node::new_pad(pad_content, NodePosition::new(1, Height::new(1)));
-        let merged = Node::merge(&leaf, &pad);
+        let pad = Node::new_pad(pad_content, NodePosition::new(1, Height::new(0)));
+        let merged = Node::merge(&leaf, &pad, &NodePosition::new(0, Height::new(1)));
         assert_eq!(merged.liability, BigUint::from(1u32));
     }
 }
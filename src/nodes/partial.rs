@@ -2,23 +2,25 @@ use std::fmt::Debug;
 
 use super::TreeNode;
 use crate::{
-    hasher::{poseidon_hash, Hashables},
+    error::ErrorKind,
+    hasher::{leaf_hash, merge_hash, pad_hash},
     node_position::NodePosition,
     pedersen::Pedersen,
+    range_proof::Committed,
+    record::Record,
+    secret::Secret,
     smt::NodeContent,
     tree_builder::PaddingNodeContent,
-    BaseField, CurvePoint,
+    BaseField, CurvePoint, ScalarField,
 };
-use ark_ec::AffineRepr;
-use ark_serialize::CanonicalSerialize;
-use mina_hasher::{create_legacy, Hasher};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Compress, Validate};
 use o1_utils::FieldHelpers;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
 /// The partial node contains partial information used in the merkle proofs to hide liabilities
 #[serde_as]
-#[derive(Clone, PartialEq, Eq, Serialize)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PartialNode {
     #[serde_as(as = "crate::serialize::SerdeAs")]
     pub commitment: CurvePoint,
@@ -30,6 +32,16 @@ impl PartialNode {
     pub fn new(commitment: CurvePoint, hash: BaseField) -> Self {
         PartialNode { commitment, hash }
     }
+
+    pub fn hash(&self) -> BaseField {
+        self.hash
+    }
+}
+
+impl Committed for PartialNode {
+    fn commitment(&self) -> CurvePoint {
+        self.commitment
+    }
 }
 
 impl Debug for PartialNode {
@@ -45,53 +57,58 @@ impl Debug for PartialNode {
 
 impl TreeNode for PartialNode {
     fn new_leaf<const N_CURR: usize>(
-        blinding_factor: crate::secret::Secret,
-        record: &crate::record::Record<N_CURR>,
-        user_salt: crate::secret::Secret,
+        blinding_factor: Secret,
+        record: &Record<N_CURR>,
+        user_salt: Secret,
     ) -> Self {
         let total_lia = record.total_liability();
-        let commitment = Pedersen::default().commit(total_lia.into(), blinding_factor.to_field());
-
-        // hash = `H("leaf" | user_id | user_salt)`
-        let mut hasher = create_legacy::<Hashables>(());
-        hasher.update(&Hashables::from_slice("leaf".as_bytes()));
-        hasher.update(&record.to_hashable());
-        hasher.update(&Hashables::Secret(user_salt));
+        let commitment = Pedersen::get().commit(total_lia.into(), blinding_factor.to_field());
         PartialNode {
-            hash: hasher.digest(),
+            hash: leaf_hash(record, user_salt),
             commitment,
         }
     }
 
-    fn new_pad(padding: PaddingNodeContent, position: NodePosition) -> Self {
-        let commitment = Pedersen::default().commit(0.into(), padding.bliding_factor().to_field());
-
-        let mut hasher = create_legacy::<Hashables>(());
+    fn new_leaves<const N_CURR: usize>(
+        leaves: Vec<(Secret, &Record<N_CURR>, Secret)>,
+    ) -> Vec<Self> {
+        let openings: Vec<(ScalarField, ScalarField)> = leaves
+            .iter()
+            .map(|(blinding_factor, record, _)| {
+                (record.total_liability().into(), blinding_factor.to_field())
+            })
+            .collect();
+        let commitments = Pedersen::get().commit_batch(&openings);
+        leaves
+            .into_iter()
+            .zip(commitments)
+            .map(|((_, record, user_salt), commitment)| PartialNode {
+                hash: leaf_hash(record, user_salt),
+                commitment,
+            })
+            .collect()
+    }
 
-        hasher.update(&Hashables::from_slice("pad".as_bytes()));
-        hasher.update(&Hashables::Position(position));
-        hasher.update(&Hashables::Secret(padding.user_secret()));
+    fn new_pad(padding: PaddingNodeContent, position: NodePosition) -> Self {
+        let commitment = Pedersen::get().commit(0.into(), padding.bliding_factor().to_field());
 
         Self {
-            hash: hasher.digest(),
+            hash: pad_hash(position, padding.user_secret()),
             commitment,
         }
     }
 
-    fn merge(left_child: &Self, right_child: &Self) -> Self {
+    fn merge(left_child: &Self, right_child: &Self, position: &NodePosition) -> Self {
         // commitment left.com + right.com
         let commitment: CurvePoint = (left_child.commitment + right_child.commitment).into();
 
-        // H = H(left.com | right.com | left.hash | right.hash )
-        let hash_inputs = [
-            *left_child.commitment.x().unwrap(),
-            *left_child.commitment.y().unwrap(),
-            *right_child.commitment.x().unwrap(),
-            *right_child.commitment.y().unwrap(),
+        let hash = merge_hash(
+            position,
+            &left_child.commitment,
             left_child.hash,
+            &right_child.commitment,
             right_child.hash,
-        ];
-        let hash = poseidon_hash(&hash_inputs);
+        );
         Self { hash, commitment }
     }
 }
@@ -111,3 +128,72 @@ impl Into<NodeContent> for PartialNode {
         }
     }
 }
+
+impl TryFrom<NodeContent> for PartialNode {
+    type Error = ErrorKind;
+
+    /// parses the uncompressed bytes and checks that the commitment is in the prime order subgroup
+    fn try_from(content: NodeContent) -> Result<Self, Self::Error> {
+        let mut commitment_bytes = content.commitment.as_slice();
+        let commitment =
+            CurvePoint::deserialize_with_mode(&mut commitment_bytes, Compress::No, Validate::No)
+                .map_err(|_| ErrorKind::ProofMalformedCurvePoint)?;
+        if !commitment_bytes.is_empty()
+            || !commitment.is_on_curve()
+            || !commitment.is_in_correct_subgroup_assuming_on_curve()
+        {
+            return Err(ErrorKind::ProofMalformedCurvePoint);
+        }
+
+        let mut hash_bytes = content.hash.as_slice();
+        let hash = BaseField::deserialize_uncompressed(&mut hash_bytes)
+            .map_err(|_| ErrorKind::ProofMalformedHash)?;
+        if !hash_bytes.is_empty() {
+            return Err(ErrorKind::ProofMalformedHash);
+        }
+        Ok(PartialNode { commitment, hash })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PartialNode;
+    use crate::{
+        error::ErrorKind,
+        node_position::{Height, NodePosition},
+        nodes::TreeNode,
+        record::Record,
+        secret::Secret,
+        smt::NodeContent,
+        tree_builder::PaddingNodeContent,
+    };
+
+    #[test]
+    fn node_content_round_trip() {
+        let record = Record::new(&[1, 2], String::from("S"));
+        let leaf = PartialNode::new_leaf(Secret::from(2u32), &record, Secret::from(1u32));
+        let pad_content = PaddingNodeContent::new(Secret::from(3u32), Secret::from(4u32));
+        let pad = PartialNode::new_pad(pad_content, NodePosition::new(1, Height::new(0)));
+        let merged = PartialNode::merge(&leaf, &pad, &NodePosition::new(0, Height::new(1)));
+
+        let content: NodeContent = merged.clone().into();
+        assert_eq!(PartialNode::try_from(content.clone()).unwrap(), merged);
+
+        let json = serde_json::to_string(&merged).unwrap();
+        assert_eq!(serde_json::from_str::<PartialNode>(&json).unwrap(), merged);
+
+        let mut not_on_curve = content.clone();
+        not_on_curve.commitment[0] ^= 1;
+        assert!(matches!(
+            PartialNode::try_from(not_on_curve),
+            Err(ErrorKind::ProofMalformedCurvePoint)
+        ));
+
+        let mut trailing = content;
+        trailing.hash.push(0);
+        assert!(matches!(
+            PartialNode::try_from(trailing),
+            Err(ErrorKind::ProofMalformedHash)
+        ));
+    }
+}
@@ -0,0 +1,113 @@
+use super::{partial::PartialNode, ServedNode, TreeNode};
+use crate::{
+    error::Result,
+    hasher::leaf_hash,
+    node_position::NodePosition,
+    pedersen::Pedersen,
+    range_proof::RangeProof,
+    record::Record,
+    secret::Secret,
+    smt::{CommitmentScheme, NodeContent},
+    tree_builder::PaddingNodeContent,
+    CurvePoint, ScalarField,
+};
+use serde::{Deserialize, Serialize};
+
+/// A partial node whose leaf commits to every asset separately
+/// the leaf commitment is `sum(balance_i * G_i) + b * H` so the root commits to the
+/// per asset totals and a user can check each of their balances was included
+/// pads and internal nodes are the same as `PartialNode`
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(transparent)]
+pub struct VectorNode(PartialNode);
+
+impl VectorNode {
+    pub fn commitment(&self) -> CurvePoint {
+        self.0.commitment
+    }
+
+    pub fn to_partial(&self) -> PartialNode {
+        self.0.clone()
+    }
+
+    /// checks the commitment opens to the given per asset balances
+    pub fn verify_opening(&self, balances: &[u64], blinding_factor: ScalarField) -> bool {
+        let values: Vec<ScalarField> = balances.iter().map(|&b| b.into()).collect();
+        Pedersen::get().commit_vector(&values, blinding_factor) == self.0.commitment
+    }
+}
+
+impl TreeNode for VectorNode {
+    fn new_leaf<const N_CURR: usize>(
+        blinding_factor: Secret,
+        record: &Record<N_CURR>,
+        user_salt: Secret,
+    ) -> Self {
+        let values: Vec<ScalarField> = record.balances().iter().map(|&b| b.into()).collect();
+        let commitment = Pedersen::get().commit_vector(&values, blinding_factor.to_field());
+        VectorNode(PartialNode::new(commitment, leaf_hash(record, user_salt)))
+    }
+
+    fn new_pad(padding_node_content: PaddingNodeContent, position: NodePosition) -> Self {
+        VectorNode(PartialNode::new_pad(padding_node_content, position))
+    }
+
+    fn merge(left_child: &Self, right_child: &Self, position: &NodePosition) -> Self {
+        VectorNode(PartialNode::merge(&left_child.0, &right_child.0, position))
+    }
+}
+
+impl ServedNode for VectorNode {
+    const SCHEME: CommitmentScheme = CommitmentScheme::PerAsset;
+
+    fn to_partial(&self) -> PartialNode {
+        VectorNode::to_partial(self)
+    }
+
+    fn range_proofs(_nodes: &[&Self]) -> Result<Vec<RangeProof>> {
+        // a range proof binds a single value to `G`, the per asset commitments are sent without
+        Ok(vec![])
+    }
+}
+
+impl From<PartialNode> for VectorNode {
+    fn from(node: PartialNode) -> Self {
+        VectorNode(node)
+    }
+}
+
+impl From<VectorNode> for NodeContent {
+    fn from(node: VectorNode) -> Self {
+        node.0.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VectorNode;
+    use crate::{
+        node_position::{Height, NodePosition},
+        nodes::TreeNode,
+        record::Record,
+        secret::Secret,
+        ScalarField,
+    };
+
+    #[test]
+    fn root_opens_to_per_asset_totals() {
+        let btc_eth_left = Record::new(&[3, 7], String::from("left"));
+        let btc_eth_right = Record::new(&[5, 11], String::from("right"));
+        let (left_blinding, right_blinding) = (Secret::from(2u32), Secret::from(9u32));
+        let left = VectorNode::new_leaf(left_blinding.clone(), &btc_eth_left, Secret::from(1u32));
+        let right =
+            VectorNode::new_leaf(right_blinding.clone(), &btc_eth_right, Secret::from(4u32));
+
+        assert!(left.verify_opening(&[3, 7], left_blinding.to_field()));
+        assert!(!left.verify_opening(&[7, 3], left_blinding.to_field()));
+
+        let parent = VectorNode::merge(&left, &right, &NodePosition::new(0, Height::new(1)));
+        let blinding: ScalarField = left_blinding.to_field() + right_blinding.to_field();
+        assert!(parent.verify_opening(&[8, 18], blinding));
+        assert!(!parent.verify_opening(&[18, 8], blinding));
+    }
+}
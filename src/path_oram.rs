@@ -0,0 +1,496 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    error::{ErrorKind, Result},
+    node_position::{Height, NodePosition},
+    nodes::TreeNode,
+    siblings::Siblings,
+    store::Store,
+    tree::SMT,
+    tree_builder::PaddingNodeContent,
+};
+
+/// Number of blocks a single bucket can hold, `Z` in the Path ORAM paper
+pub const BUCKET_SIZE: usize = 4;
+
+/// The maximum number of blocks allowed to stay in the stash after an eviction
+/// with `Z = 4` the probability of crossing this bound is negligible
+pub const MAX_STASH_SIZE: usize = 128;
+
+/// A physical access to a bucket, the index is the bucket index in heap order (root is 0)
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read(u64),
+    Write(u64),
+}
+
+#[derive(Clone, Debug)]
+struct Block<T> {
+    pos: NodePosition,
+    leaf: u64,
+    node: T,
+}
+
+#[derive(Clone, Debug)]
+struct Bucket<T> {
+    blocks: Vec<Block<T>>,
+}
+
+impl<T> Default for Bucket<T> {
+    fn default() -> Self {
+        Self {
+            blocks: Vec::with_capacity(BUCKET_SIZE),
+        }
+    }
+}
+
+/// Path ORAM over the tree nodes
+/// every logical access reads a whole root to leaf path of buckets into the stash,
+/// remaps the block to a fresh random leaf and writes the same path back
+/// so the physical access pattern does not depend on which node was touched
+#[derive(Clone, Debug)]
+pub struct PathOram<T: TreeNode + Clone + Debug> {
+    buckets: Vec<Bucket<T>>,
+    /// height of the bucket tree, there are `2^levels` leaves
+    levels: u8,
+    position_map: HashMap<NodePosition, u64>,
+    stash: Vec<Block<T>>,
+    rng: StdRng,
+    /// only recorded in tests, a server would grow it on every access
+    #[cfg(test)]
+    trace: Vec<Access>,
+}
+
+impl<T> PathOram<T>
+where
+    T: TreeNode + Clone + Debug,
+{
+    /// creates an empty ORAM that can hold `capacity` nodes
+    pub fn new(capacity: u64) -> Self {
+        Self::with_rng(capacity, StdRng::from_os_rng())
+    }
+
+    pub fn with_rng(capacity: u64, rng: StdRng) -> Self {
+        let levels = capacity.max(2).next_power_of_two().ilog2() as u8;
+        let num_buckets = (1usize << (levels + 1)) - 1;
+        Self {
+            buckets: (0..num_buckets).map(|_| Bucket::default()).collect(),
+            levels,
+            position_map: HashMap::new(),
+            stash: vec![],
+            rng,
+            #[cfg(test)]
+            trace: vec![],
+        }
+    }
+
+    /// moves all the nodes of the store into a new ORAM
+    pub fn from_store(store: Store<T>, rng: StdRng) -> Result<Self> {
+        let mut oram = Self::with_rng(store.len() as u64, rng);
+        for (pos, node) in store.map {
+            oram.insert(pos, node)?;
+        }
+        #[cfg(test)]
+        oram.clear_trace();
+        Ok(oram)
+    }
+
+    /// returns the number of leaves of the bucket tree
+    pub fn num_leaves(&self) -> u64 {
+        1u64 << self.levels
+    }
+
+    pub fn levels(&self) -> u8 {
+        self.levels
+    }
+
+    pub fn len(&self) -> usize {
+        self.position_map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position_map.is_empty()
+    }
+
+    pub fn contains(&self, pos: &NodePosition) -> bool {
+        self.position_map.contains_key(pos)
+    }
+
+    pub fn stash_len(&self) -> usize {
+        self.stash.len()
+    }
+
+    /// the physical bucket accesses since the last `clear_trace`
+    #[cfg(test)]
+    pub fn trace(&self) -> &[Access] {
+        &self.trace
+    }
+
+    #[cfg(test)]
+    pub fn clear_trace(&mut self) {
+        self.trace.clear();
+    }
+
+    /// inserts a new node, the node is placed in the stash and evicted along a random path
+    pub fn insert(&mut self, pos: NodePosition, node: T) -> Result<()> {
+        if self.contains(&pos) {
+            self.write(pos, node)?;
+            return Ok(());
+        }
+        let leaf = self.random_leaf();
+        self.position_map.insert(pos, leaf);
+        self.stash.push(Block { pos, leaf, node });
+        let evict_leaf = self.random_leaf();
+        self.read_path(evict_leaf);
+        self.write_path(evict_leaf)
+    }
+
+    /// oblivious read of the node at `pos`
+    pub fn read(&mut self, pos: &NodePosition) -> Result<T> {
+        self.access(pos, None)
+    }
+
+    /// oblivious write of the node at `pos`, returns the replaced node
+    pub fn write(&mut self, pos: NodePosition, node: T) -> Result<T> {
+        self.access(&pos, Some(node))
+    }
+
+    /// oblivious read of a node which may be missing
+    /// a random path is accessed for a missing node so both cases look the same
+    pub fn try_read(&mut self, pos: &NodePosition) -> Result<Option<T>> {
+        if self.contains(pos) {
+            return self.read(pos).map(Some);
+        }
+        let leaf = self.random_leaf();
+        self.read_path(leaf);
+        self.write_path(leaf)?;
+        Ok(None)
+    }
+
+    /// copies every node into a plain store, the ORAM is left untouched
+    pub fn to_store(&self) -> Store<T> {
+        let map = self
+            .buckets
+            .iter()
+            .flat_map(|bucket| bucket.blocks.iter())
+            .chain(self.stash.iter())
+            .map(|block| (block.pos, block.node.clone()))
+            .collect();
+        Store { map }
+    }
+
+    /// moves every node back into a plain store
+    pub fn into_store(self) -> Store<T> {
+        let map = self
+            .buckets
+            .into_iter()
+            .flat_map(|bucket| bucket.blocks)
+            .chain(self.stash)
+            .map(|block| (block.pos, block.node))
+            .collect();
+        Store { map }
+    }
+
+    fn access(&mut self, pos: &NodePosition, new_node: Option<T>) -> Result<T> {
+        let leaf = *self
+            .position_map
+            .get(pos)
+            .ok_or(ErrorKind::OramBlockNotFound(*pos))?;
+        let new_leaf = self.random_leaf();
+        self.position_map.insert(*pos, new_leaf);
+
+        self.read_path(leaf);
+        let block = self
+            .stash
+            .iter_mut()
+            .find(|block| block.pos == *pos)
+            .ok_or(ErrorKind::OramBlockNotFound(*pos))?;
+        block.leaf = new_leaf;
+        let node = match new_node {
+            Some(node) => std::mem::replace(&mut block.node, node),
+            None => block.node.clone(),
+        };
+        self.write_path(leaf)?;
+        Ok(node)
+    }
+
+    fn random_leaf(&mut self) -> u64 {
+        self.rng.random_range(0..self.num_leaves())
+    }
+
+    /// index in heap order of the bucket at `level` (root is level 0) on the path to `leaf`
+    fn bucket_index(&self, leaf: u64, level: u8) -> usize {
+        ((1usize << level) - 1) + (leaf >> (self.levels - level)) as usize
+    }
+
+    fn read_path(&mut self, leaf: u64) {
+        for level in 0..=self.levels {
+            let idx = self.bucket_index(leaf, level);
+            #[cfg(test)]
+            self.trace.push(Access::Read(idx as u64));
+            self.stash.append(&mut self.buckets[idx].blocks);
+        }
+    }
+
+    /// greedily evicts the stash from the deepest bucket of the path to the root
+    fn write_path(&mut self, leaf: u64) -> Result<()> {
+        for level in (0..=self.levels).rev() {
+            let idx = self.bucket_index(leaf, level);
+            let shift = self.levels - level;
+            let mut i = 0;
+            while i < self.stash.len() && self.buckets[idx].blocks.len() < BUCKET_SIZE {
+                if self.stash[i].leaf >> shift == leaf >> shift {
+                    let block = self.stash.swap_remove(i);
+                    self.buckets[idx].blocks.push(block);
+                } else {
+                    i += 1;
+                }
+            }
+            #[cfg(test)]
+            self.trace.push(Access::Write(idx as u64));
+        }
+        if self.stash.len() > MAX_STASH_SIZE {
+            return Err(ErrorKind::OramStashOverflow(self.stash.len()));
+        }
+        Ok(())
+    }
+}
+
+impl<T: TreeNode + Clone + Debug> From<PathOram<T>> for Store<T> {
+    fn from(oram: PathOram<T>) -> Self {
+        oram.into_store()
+    }
+}
+
+/// A tree with every node below the root kept in a `PathOram`
+/// reading a path makes `height + 1` accesses and updating a leaf `2 * height`
+/// whichever leaf is touched, missing siblings are padding and cost a dummy access
+#[derive(Debug)]
+pub struct OramTree<T: TreeNode + Clone + Debug + Serialize> {
+    pub root: T,
+    pub height: Height,
+    oram: PathOram<T>,
+}
+
+impl<T> OramTree<T>
+where
+    T: TreeNode + Clone + Debug + Serialize,
+{
+    /// moves the nodes of `tree` into an ORAM
+    /// every level must be stored, a sibling missing from the ORAM is taken to be padding
+    pub fn from_tree(tree: SMT<T>, rng: StdRng) -> Result<Self> {
+        if tree.store_depth.saturating_add(1) < tree.height.as_u8() {
+            return Err(ErrorKind::OramPartialStore {
+                store_depth: tree.store_depth,
+                height: tree.height.as_u8(),
+            });
+        }
+        Ok(Self {
+            root: tree.root,
+            height: tree.height,
+            oram: PathOram::from_store(tree.store, rng)?,
+        })
+    }
+
+    /// the leaf at `leaf_pos` along with its siblings and their sides
+    pub fn path<F: Fn(&NodePosition) -> PaddingNodeContent>(
+        &mut self,
+        leaf_pos: NodePosition,
+        padding_fn: &F,
+    ) -> Result<(T, Siblings<T>, Vec<bool>)> {
+        let leaf = self
+            .oram
+            .try_read(&leaf_pos)?
+            .ok_or(ErrorKind::CannotFindLeafNode(leaf_pos))?;
+        let (siblings, lefts) = self.siblings(leaf_pos, padding_fn)?;
+        Ok((leaf, siblings, lefts))
+    }
+
+    /// replaces the leaf at `leaf_pos` and recomputes its ancestors
+    /// returns the written nodes from the leaf up to the new root
+    pub fn update_leaf<F: Fn(&NodePosition) -> PaddingNodeContent>(
+        &mut self,
+        leaf_pos: NodePosition,
+        leaf: T,
+        padding_fn: &F,
+    ) -> Result<Vec<(NodePosition, T)>> {
+        if !self.oram.contains(&leaf_pos) {
+            return Err(ErrorKind::CannotFindLeafNode(leaf_pos));
+        }
+        self.oram.write(leaf_pos, leaf.clone())?;
+        let (siblings, lefts) = self.siblings(leaf_pos, padding_fn)?;
+
+        let mut written = Vec::with_capacity(siblings.0.len() + 1);
+        written.push((leaf_pos, leaf));
+        for (sibling, left) in siblings.0.iter().zip(lefts) {
+            let (pos, node) = written.last().unwrap();
+            let parent_pos = pos.get_parent_node_pos();
+            let parent = if left {
+                T::merge(sibling, node, &parent_pos)
+            } else {
+                T::merge(node, sibling, &parent_pos)
+            };
+            // the root is kept out of the ORAM
+            if parent_pos.1 != self.height {
+                self.oram.write(parent_pos, parent.clone())?;
+            }
+            written.push((parent_pos, parent));
+        }
+        self.root = written.last().unwrap().1.clone();
+        Ok(written)
+    }
+
+    /// copies the nodes back into a tree, the ORAM is left untouched
+    pub fn to_tree(&self) -> SMT<T> {
+        SMT {
+            root: self.root.clone(),
+            store: self.oram.to_store(),
+            height: self.height,
+            store_depth: self.height.as_u8(),
+        }
+    }
+
+    fn siblings<F: Fn(&NodePosition) -> PaddingNodeContent>(
+        &mut self,
+        leaf_pos: NodePosition,
+        padding_fn: &F,
+    ) -> Result<(Siblings<T>, Vec<bool>)> {
+        let mut siblings = Vec::with_capacity(self.height.as_u8() as usize);
+        let mut lefts = Vec::with_capacity(self.height.as_u8() as usize);
+        let mut current_pos = leaf_pos;
+        for _ in 0..self.height.as_u8() {
+            let sibling_pos = current_pos.get_sibling_pos();
+            lefts.push(sibling_pos.is_left());
+            let sibling = match self.oram.try_read(&sibling_pos)? {
+                Some(node) => node,
+                None => T::new_pad(padding_fn(&sibling_pos), sibling_pos),
+            };
+            siblings.push(sibling);
+            current_pos = current_pos.get_parent_node_pos();
+        }
+        Ok((Siblings(siblings), lefts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{Access, OramTree, PathOram};
+    use crate::{
+        node_position::{Height, NodePosition},
+        nodes::partial::PartialNode,
+        proofs::MerkleWitness,
+        record::{random_records, Record},
+        salt::Salt,
+        secret::random_secret,
+        tree::{new_leaf_node, TreeBuilder, TreeParams},
+    };
+
+    /// the trace reduced to the operation and the level of every bucket touched
+    fn trace_shape(trace: &[Access]) -> Vec<(bool, u32)> {
+        trace
+            .iter()
+            .map(|access| match access {
+                Access::Read(idx) => (true, (idx + 1).ilog2()),
+                Access::Write(idx) => (false, (idx + 1).ilog2()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trace_is_independent_of_updated_user() {
+        let records = random_records::<3>(6);
+        let tree_params = TreeParams {
+            master_secret: random_secret(),
+            salt_s: Salt::generate_random(),
+            salt_b: Salt::generate_random(),
+        };
+        let mut tree_builder: TreeBuilder<PartialNode, 3> =
+            TreeBuilder::new(records.clone(), Height::new(3), tree_params);
+        let (tree, record_map) = tree_builder.build_single_threaded(Some(0)).unwrap();
+        let expected_len = tree.store.len();
+
+        let mut oram = PathOram::from_store(tree.store, StdRng::seed_from_u64(7)).unwrap();
+        let levels = oram.levels() as usize;
+        let mut shapes = HashSet::new();
+        let mut leaves_read = HashSet::new();
+        for _ in 0..64 {
+            for record in &records {
+                let pos = record_map[&record.hashed_email];
+                let node = oram.read(&pos).unwrap();
+                oram.write(pos, node).unwrap();
+
+                let trace = oram.trace().to_vec();
+                oram.clear_trace();
+                // two accesses each reading and writing back a full path
+                assert_eq!(trace.len(), 4 * (levels + 1));
+                if let Access::Read(idx) = trace[levels] {
+                    leaves_read.insert(idx);
+                }
+                shapes.insert(trace_shape(&trace));
+            }
+        }
+        assert_eq!(shapes.len(), 1);
+        // the path read is drawn fresh on every access
+        assert!(leaves_read.len() as u64 > oram.num_leaves() / 2);
+
+        let store = oram.into_store();
+        assert_eq!(store.len(), expected_len);
+    }
+
+    #[test]
+    fn oram_tree_matches_plain_tree() {
+        let records = random_records::<3>(6);
+        let tree_params = TreeParams {
+            master_secret: random_secret(),
+            salt_s: Salt::generate_random(),
+            salt_b: Salt::generate_random(),
+        };
+        // seeded so every build places the users at the same leaves
+        let mut tree_builder: TreeBuilder<PartialNode, 3> =
+            TreeBuilder::new(records.clone(), Height::new(4), tree_params.clone()).with_epoch(1);
+        let (mut tree, record_map) = tree_builder.build_single_threaded(Some(4)).unwrap();
+        let (oram_tree, _) = tree_builder.build_single_threaded(Some(4)).unwrap();
+        let mut oram_tree = OramTree::from_tree(oram_tree, StdRng::seed_from_u64(7)).unwrap();
+        let (partial_tree, _) = tree_builder.build_single_threaded(Some(0)).unwrap();
+        assert!(OramTree::from_tree(partial_tree, StdRng::seed_from_u64(7)).is_err());
+
+        let padding_fn = |pos: &NodePosition| tree_params.padding_node_content(pos);
+        let mut trace_lens = HashSet::new();
+        for (i, record) in records.iter().enumerate() {
+            let user = record.hashed_email.clone();
+            let new_record = Record::new(&[i as u64, 1, 2], user.clone());
+            let expected_root = tree
+                .update_leaf(&user, &new_record, &record_map, &tree_params)
+                .unwrap();
+
+            let pos = record_map[&user];
+            let (_, leaf) =
+                new_leaf_node::<PartialNode, 3>(&tree_params, &new_record, pos.x_cord());
+            oram_tree.oram.clear_trace();
+            let written = oram_tree.update_leaf(pos, leaf, &padding_fn).unwrap();
+            assert_eq!(written.len(), 5);
+            assert_eq!(written.last().unwrap().1, expected_root);
+
+            let witness: MerkleWitness<PartialNode, 3> = MerkleWitness::generate_oblivious_witness(
+                user,
+                &mut oram_tree,
+                &record_map,
+                &padding_fn,
+            )
+            .unwrap();
+            witness.verify(&expected_root).unwrap();
+            trace_lens.insert(oram_tree.oram.trace().len());
+        }
+        // the same number of accesses for every user
+        assert_eq!(trace_lens.len(), 1);
+        assert_eq!(oram_tree.to_tree().store.map, tree.store.map);
+    }
+}
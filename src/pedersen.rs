@@ -1,33 +1,241 @@
-use crate::{CurvePoint, ScalarField};
-use ark_ec::AffineRepr;
-use ark_serialize::CanonicalSerializeHashExt;
+use crate::{BaseField, CurvePoint, ScalarField};
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{BigInteger, PrimeField, Zero};
 use mina_curves::pasta::curves::pallas::{G_GENERATOR_X, G_GENERATOR_Y};
-use num_bigint::BigUint;
-use std::ops::Mul;
+use sha2::{Digest, Sha256};
+use std::{ops::Mul, sync::OnceLock};
+
+/// Domain of the blinding generator `H`
+pub const BLINDING_DOMAIN: &[u8] = b"oram-smst pedersen H";
+/// Domain of the per asset generators, followed by the asset index
+pub const ASSET_DOMAIN: &[u8] = b"oram-smst pedersen asset";
+/// Number of asset generators derived once with the static instance
+const CACHED_ASSETS: usize = 16;
+
+/// Bits of the scalar handled by one window of a fixed base table
+const WINDOW_BITS: usize = 8;
+
+static PEDERSEN: OnceLock<Pedersen> = OnceLock::new();
+static TABLES: OnceLock<CommitTables> = OnceLock::new();
+
+type Projective = <CurvePoint as AffineRepr>::Group;
 
 /// Represents pair of base points in  pallas curve that act as keys
+#[derive(Clone, Debug)]
 pub struct Pedersen {
     /// Base point for the commited value default as the generator
     pub base: CurvePoint,
-    /// Base point for the bliding factor defaults as `hash(gen) * Gen`
+    /// Base point for the bliding factor, `hash_to_curve("oram-smst pedersen H")`
+    /// so nobody knows its discrete log relative to `base`
     pub base_blinding: CurvePoint,
+    asset_generators: Vec<CurvePoint>,
 }
 impl Default for Pedersen {
     /// The base point
     fn default() -> Self {
-        let gen = CurvePoint::new(G_GENERATOR_X, G_GENERATOR_Y);
-        let gen_hash = gen.hash::<sha2::Sha256>();
-        let gen_hash_scalar = BigUint::from_bytes_le(&gen_hash);
-        let base_blinding = gen.mul_bigint(gen_hash_scalar.to_u64_digits());
-        Pedersen {
-            base: gen,
-            base_blinding: base_blinding.into(),
-        }
+        Self::get().clone()
     }
 }
 impl Pedersen {
+    /// the generators derived once and shared by every commitment
+    pub fn get() -> &'static Pedersen {
+        PEDERSEN.get_or_init(|| Pedersen {
+            base: CurvePoint::new(G_GENERATOR_X, G_GENERATOR_Y),
+            base_blinding: hash_to_curve(BLINDING_DOMAIN, &[]),
+            asset_generators: (0..CACHED_ASSETS).map(derive_asset_generator).collect(),
+        })
+    }
+
     pub fn commit(&self, value: ScalarField, blinding: ScalarField) -> CurvePoint {
-        let res = self.base.mul(&value) + self.base_blinding.mul(&blinding);
+        let res = match self.tables() {
+            Some(tables) => tables.commit(&value, &blinding),
+            None => self.base.mul(&value) + self.base_blinding.mul(&blinding),
+        };
         res.into()
     }
+
+    /// commits to every `(value, blinding)` pair
+    /// uses the fixed base tables and converts all the commitments to affine with a single inversion
+    pub fn commit_batch(&self, openings: &[(ScalarField, ScalarField)]) -> Vec<CurvePoint> {
+        let custom_tables;
+        let tables = match self.tables() {
+            Some(tables) => tables,
+            None => {
+                custom_tables = CommitTables::new(self);
+                &custom_tables
+            }
+        };
+        let commitments: Vec<Projective> = openings
+            .iter()
+            .map(|(value, blinding)| tables.commit(value, blinding))
+            .collect();
+        Projective::normalize_batch(&commitments)
+    }
+
+    /// the cached tables, only valid for the default generators
+    fn tables(&self) -> Option<&'static CommitTables> {
+        let default = Self::get();
+        (self.base == default.base && self.base_blinding == default.base_blinding)
+            .then(|| TABLES.get_or_init(|| CommitTables::new(default)))
+    }
+
+    /// the generator for the asset at `index`, `hash_to_curve("oram-smst pedersen asset" | index)`
+    pub fn asset_generator(&self, index: usize) -> CurvePoint {
+        self.asset_generators
+            .get(index)
+            .copied()
+            .unwrap_or_else(|| derive_asset_generator(index))
+    }
+
+    /// the generators for the first `n` assets
+    pub fn asset_generators(&self, n: usize) -> Vec<CurvePoint> {
+        (0..n).map(|index| self.asset_generator(index)).collect()
+    }
+
+    /// vector commitment `sum(v_i * G_i) + r * H` with one generator per asset
+    pub fn commit_vector(&self, values: &[ScalarField], blinding: ScalarField) -> CurvePoint {
+        let res = self.asset_generators(values.len()).iter().zip(values).fold(
+            self.base_blinding.mul(&blinding),
+            |acc, (generator, value)| acc + generator.mul(value),
+        );
+        res.into_affine()
+    }
+}
+
+/// Fixed base tables for both generators of a `Pedersen`
+struct CommitTables {
+    base: FixedBaseTable,
+    blinding: FixedBaseTable,
+}
+
+impl CommitTables {
+    fn new(pedersen: &Pedersen) -> Self {
+        CommitTables {
+            base: FixedBaseTable::new(pedersen.base),
+            blinding: FixedBaseTable::new(pedersen.base_blinding),
+        }
+    }
+
+    fn commit(&self, value: &ScalarField, blinding: &ScalarField) -> Projective {
+        self.base.mul(value) + self.blinding.mul(blinding)
+    }
+}
+
+/// The multiples `j * 2^(8 * i) * P` for every window `i` and every byte `j`
+/// a scalar multiplication is then one mixed addition per byte of the scalar
+struct FixedBaseTable {
+    windows: Vec<Vec<CurvePoint>>,
+}
+
+impl FixedBaseTable {
+    fn new(base: CurvePoint) -> Self {
+        let num_windows = (ScalarField::MODULUS_BIT_SIZE as usize).div_ceil(WINDOW_BITS);
+        let mut window_base = base.into_group();
+        let mut windows = Vec::with_capacity(num_windows);
+        for _ in 0..num_windows {
+            let mut multiples = Vec::with_capacity(1 << WINDOW_BITS);
+            let mut multiple = Projective::zero();
+            for _ in 0..(1 << WINDOW_BITS) {
+                multiples.push(multiple);
+                multiple += window_base;
+            }
+            windows.push(Projective::normalize_batch(&multiples));
+            // `2^8 * window_base` is the base of the next window
+            window_base = multiple;
+        }
+        FixedBaseTable { windows }
+    }
+
+    fn mul(&self, scalar: &ScalarField) -> Projective {
+        scalar
+            .into_bigint()
+            .to_bytes_le()
+            .iter()
+            .zip(&self.windows)
+            .fold(Projective::zero(), |acc, (byte, multiples)| {
+                acc + multiples[*byte as usize]
+            })
+    }
+}
+
+fn derive_asset_generator(index: usize) -> CurvePoint {
+    hash_to_curve(ASSET_DOMAIN, &(index as u64).to_le_bytes())
+}
+
+/// try and increment hash to curve
+/// `x = sha256(domain | msg | counter)` read little-endian modulo the base field, with the
+/// 8 byte little-endian `counter` starting at 0 and incremented until `x^3 + 5` is a square,
+/// the point takes the smaller of the two `y`, pallas has cofactor 1 so every point is in the group
+pub fn hash_to_curve(domain: &[u8], msg: &[u8]) -> CurvePoint {
+    let mut counter = 0u64;
+    loop {
+        let mut hasher = Sha256::new();
+        hasher.update(domain);
+        hasher.update(msg);
+        hasher.update(counter.to_le_bytes());
+        let x = BaseField::from_le_bytes_mod_order(&hasher.finalize());
+        if let Some(point) = CurvePoint::get_point_from_x_unchecked(x, false) {
+            return point;
+        }
+        counter += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Mul;
+
+    use ark_ec::CurveGroup;
+    use ark_ff::UniformRand;
+    use num_bigint::BigUint;
+
+    use super::{hash_to_curve, Pedersen, ASSET_DOMAIN, BLINDING_DOMAIN};
+    use crate::{BaseField, CurvePoint, ScalarField};
+
+    fn point(x: &str, y: &str) -> CurvePoint {
+        let parse = |v: &str| BaseField::from(BigUint::parse_bytes(v.as_bytes(), 10).unwrap());
+        CurvePoint::new(parse(x), parse(y))
+    }
+
+    /// vectors computed independently in python, both found at counter 0
+    #[test]
+    fn generators_are_nothing_up_my_sleeve() {
+        let pedersen = Pedersen::get();
+        let h = point(
+            "28644518113160307693799126918988236757108323090310950775925579387676686562750",
+            "7918825775590769890585899746805103035426634857842819197294103772972398072300",
+        );
+        assert_eq!(pedersen.base_blinding, h);
+        assert_eq!(hash_to_curve(BLINDING_DOMAIN, &[]), h);
+
+        let asset_0 = point(
+            "13083940903750998543497201433206760400562121707332388779258972094465320742119",
+            "3069468861581568744192019019412514812037278980676161533597454292189998030454",
+        );
+        assert_eq!(pedersen.asset_generator(0), asset_0);
+        assert_eq!(hash_to_curve(ASSET_DOMAIN, &0u64.to_le_bytes()), asset_0);
+        assert_eq!(
+            pedersen.asset_generator(100),
+            hash_to_curve(ASSET_DOMAIN, &100u64.to_le_bytes())
+        );
+    }
+
+    #[test]
+    fn batch_commitments_match_scalar_multiplication() {
+        let mut rng = ark_std::rand::thread_rng();
+        let pedersen = Pedersen::get();
+        let mut openings: Vec<(ScalarField, ScalarField)> = (0..20)
+            .map(|_| (ScalarField::rand(&mut rng), ScalarField::rand(&mut rng)))
+            .collect();
+        openings.push((ScalarField::from(0u64), ScalarField::from(0u64)));
+        openings.push((ScalarField::from(u64::MAX), -ScalarField::from(1u64)));
+
+        let batch = pedersen.commit_batch(&openings);
+        for ((value, blinding), commitment) in openings.iter().zip(batch) {
+            let expected =
+                (pedersen.base.mul(value) + pedersen.base_blinding.mul(blinding)).into_affine();
+            assert_eq!(commitment, expected);
+            assert_eq!(pedersen.commit(*value, *blinding), expected);
+        }
+    }
 }
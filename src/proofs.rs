@@ -1,24 +1,40 @@
-use std::{fmt::Debug, fs::OpenOptions, io::Write};
+use std::{
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::{BufReader, Write},
+};
 
-use serde::Serialize;
+use ark_serialize::CanonicalDeserialize;
+use prost::Message;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
     error::{ErrorKind, Result},
     node_position::NodePosition,
-    nodes::TreeNode,
+    nodes::{partial::PartialNode, vector::VectorNode, ServedNode, TreeNode},
+    path_oram::OramTree,
+    range_proof::{Committed, RangeProof},
     siblings::Siblings,
+    smt::{CommitmentScheme, Proof},
     tree::{RecordMap, SMT},
     tree_builder::PaddingNodeContent,
+    ScalarField,
 };
 
+/// The maximum length of a path as the x cordinates are `u64`
+pub const MAX_PATH_LENGTH: usize = 64;
+
 ///Creates a merkle witness for the given leaf node in JSON form and writes to the given path or defaults to proofs/user_id.json
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct MerkleWitness<T: TreeNode + Clone + Debug + Serialize, const N_CURR: usize> {
     pub path: Siblings<T>,
     pub lefts: Vec<bool>,
     pub user_leaf: T,
     pub root: T,
-    #[serde(skip_serializing)]
+    /// range proofs for the user leaf followed by every node of the path
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub range_proofs: Vec<RangeProof>,
+    #[serde(skip_serializing, default)]
     pub _user_id: String,
 }
 
@@ -40,6 +56,28 @@ impl<T: TreeNode + Clone + Debug + Serialize, const N_CURR: usize> MerkleWitness
             user_leaf,
             lefts,
             root: tree.root.clone(),
+            range_proofs: vec![],
+            _user_id: user_id,
+        })
+    }
+
+    /// same as `generate_witness` but the nodes are read through the ORAM of `tree`
+    pub fn generate_oblivious_witness<F: Fn(&NodePosition) -> PaddingNodeContent>(
+        user_id: String,
+        tree: &mut OramTree<T>,
+        record_map: &RecordMap,
+        padding_fn: &F,
+    ) -> Result<MerkleWitness<T, N_CURR>> {
+        let node_pos = record_map
+            .get(&user_id)
+            .ok_or(ErrorKind::UserNotFound(user_id.clone()))?;
+        let (user_leaf, siblings, lefts) = tree.path(*node_pos, padding_fn)?;
+        Ok(MerkleWitness {
+            path: siblings,
+            user_leaf,
+            lefts,
+            root: tree.root.clone(),
+            range_proofs: vec![],
             _user_id: user_id,
         })
     }
@@ -56,3 +94,353 @@ impl<T: TreeNode + Clone + Debug + Serialize, const N_CURR: usize> MerkleWitness
         Ok(())
     }
 }
+
+impl<T: TreeNode + Clone + Debug + Serialize + DeserializeOwned, const N_CURR: usize>
+    MerkleWitness<T, N_CURR>
+{
+    /// reads a witness written by `save`
+    pub fn load(path: &str) -> Result<Self> {
+        let file = File::open(path).map_err(|err| ErrorKind::CannotReadProof(err.to_string()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| ErrorKind::ProofMalformed(err.to_string()))
+    }
+}
+
+impl<T: TreeNode + Clone + Debug + Serialize + PartialEq, const N_CURR: usize>
+    MerkleWitness<T, N_CURR>
+{
+    /// verifies that the path from the user leaf hashes upto `expected_root`
+    pub fn verify(&self, expected_root: &T) -> Result<()> {
+        if self.root != *expected_root {
+            return Err(ErrorKind::ProofRootMismatch);
+        }
+        verify_path(
+            &self.path,
+            &self.lefts,
+            self.user_leaf.clone(),
+            expected_root,
+        )
+    }
+}
+
+impl<T: TreeNode + Clone + Debug + Serialize + Committed, const N_CURR: usize>
+    MerkleWitness<T, N_CURR>
+{
+    /// verifies the attached range proofs against the commitments of the user leaf and the path
+    pub fn verify_range_proofs(&self) -> Result<()> {
+        verify_range_proofs(&self.user_leaf, &self.path, &self.range_proofs)
+    }
+}
+
+impl<T: ServedNode, const N_CURR: usize> MerkleWitness<T, N_CURR> {
+    /// proves every commitment of the user leaf and the path is in `[0, 2^64)`
+    /// nothing is attached for nodes whose commitments cannot be range proven
+    pub fn attach_range_proofs(&mut self) -> Result<()> {
+        let nodes: Vec<&T> = std::iter::once(&self.user_leaf)
+            .chain(self.path.0.iter())
+            .collect();
+        self.range_proofs = T::range_proofs(&nodes)?;
+        Ok(())
+    }
+
+    /// strips the openings so the witness can be sent to the user
+    pub fn to_partial(&self) -> MerkleWitness<PartialNode, N_CURR> {
+        MerkleWitness {
+            path: Siblings(self.path.0.iter().map(T::to_partial).collect()),
+            lefts: self.lefts.clone(),
+            user_leaf: self.user_leaf.to_partial(),
+            root: self.root.to_partial(),
+            range_proofs: self.range_proofs.clone(),
+            _user_id: self._user_id.clone(),
+        }
+    }
+}
+
+/// verifies a `Proof` message as returned by the grpc server
+/// the proof must be requested with `fetch_user_node` so it contains the user leaf
+/// and every commitment of the user leaf and the path must come with its range proof
+pub fn verify_encoded_proof(bytes: &[u8], expected_root: &PartialNode) -> Result<()> {
+    let (proof, user_leaf, path) = decode_proof(bytes, expected_root, CommitmentScheme::Total)?;
+    let range_proofs = proof
+        .range_proofs
+        .into_iter()
+        .map(RangeProof::try_from)
+        .collect::<Result<Vec<_>>>()?;
+    verify_range_proofs(&user_leaf, &path, &range_proofs)
+}
+
+/// verifies a `Proof` message as returned by a grpc server with per asset commitments
+/// the user leaf must open to `balances` with the blinding factor of the proof, the per asset
+/// commitments come without range proofs
+pub fn verify_encoded_vector_proof(
+    bytes: &[u8],
+    expected_root: &PartialNode,
+    balances: &[u64],
+) -> Result<()> {
+    let (proof, user_leaf, _) = decode_proof(bytes, expected_root, CommitmentScheme::PerAsset)?;
+    let mut reader = proof.blinding_factor.as_slice();
+    let blinding_factor = ScalarField::deserialize_uncompressed(&mut reader)
+        .map_err(|_| ErrorKind::ProofMalformedBlindingFactor)?;
+    if !reader.is_empty() {
+        return Err(ErrorKind::ProofMalformedBlindingFactor);
+    }
+    if !VectorNode::from(user_leaf).verify_opening(balances, blinding_factor) {
+        return Err(ErrorKind::ProofOpeningMismatch);
+    }
+    Ok(())
+}
+
+/// decodes a `Proof` message and checks its path leads from the user leaf to `expected_root`
+fn decode_proof(
+    bytes: &[u8],
+    expected_root: &PartialNode,
+    scheme: CommitmentScheme,
+) -> Result<(Proof, PartialNode, Siblings<PartialNode>)> {
+    let mut proof =
+        Proof::decode(bytes).map_err(|err| ErrorKind::ProofMalformed(err.to_string()))?;
+    if proof.commitments != scheme as i32 {
+        let given = CommitmentScheme::try_from(proof.commitments)
+            .map(|given| given.as_str_name().to_string())
+            .unwrap_or_else(|_| proof.commitments.to_string());
+        return Err(ErrorKind::ProofSchemeMismatch {
+            given,
+            expected: scheme.as_str_name().to_string(),
+        });
+    }
+    let user_leaf: PartialNode = proof
+        .user_node
+        .take()
+        .ok_or(ErrorKind::ProofMissingField("user_node".to_string()))?
+        .try_into()?;
+    if let Some(root) = proof.root.take() {
+        if PartialNode::try_from(root)? != *expected_root {
+            return Err(ErrorKind::ProofRootMismatch);
+        }
+    }
+    let path = Siblings(
+        std::mem::take(&mut proof.path)
+            .into_iter()
+            .map(PartialNode::try_from)
+            .collect::<Result<Vec<_>>>()?,
+    );
+    verify_path(&path, &proof.lefts, user_leaf.clone(), expected_root)?;
+    Ok((proof, user_leaf, path))
+}
+
+/// the range proofs of the user leaf followed by every node of the path
+fn verify_range_proofs<T: TreeNode + Clone + Debug + Serialize + Committed>(
+    user_leaf: &T,
+    path: &Siblings<T>,
+    range_proofs: &[RangeProof],
+) -> Result<()> {
+    let expected = path.0.len() + 1;
+    if range_proofs.len() != expected {
+        return Err(ErrorKind::RangeProofMissing {
+            given: range_proofs.len(),
+            expected,
+        });
+    }
+    std::iter::once(user_leaf)
+        .chain(path.0.iter())
+        .zip(range_proofs)
+        .try_for_each(|(node, proof)| proof.verify(&node.commitment()))
+}
+
+fn verify_path<T: TreeNode + Clone + Debug + Serialize + PartialEq>(
+    path: &Siblings<T>,
+    lefts: &[bool],
+    user_leaf: T,
+    expected_root: &T,
+) -> Result<()> {
+    if path.0.is_empty() || path.0.len() > MAX_PATH_LENGTH {
+        return Err(ErrorKind::ProofWrongPathLength {
+            given: path.0.len(),
+            max: MAX_PATH_LENGTH,
+        });
+    }
+    if lefts.len() != path.0.len() {
+        return Err(ErrorKind::ProofLeftsMismatch {
+            lefts: lefts.len(),
+            path: path.0.len(),
+        });
+    }
+    if path.get_root_from_path(user_leaf, lefts) != *expected_root {
+        return Err(ErrorKind::ProofRootMismatch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::{verify_encoded_proof, MerkleWitness};
+    use crate::{
+        error::ErrorKind,
+        node_position::{Height, NodePosition},
+        nodes::{node::Node, partial::PartialNode},
+        record::Record,
+        salt::Salt,
+        secret::random_secret,
+        smt::{CommitmentScheme, Proof},
+        tree::{TreeBuilder, TreeParams},
+    };
+
+    /// the witness of a user with its range proofs attached, as sent by the server
+    fn witness() -> (MerkleWitness<PartialNode, 3>, PartialNode) {
+        // random balances would overflow 64 bits once summed
+        let records: Vec<Record<3>> = (1..=6u64)
+            .map(|i| Record::new(&[i, 2 * i, 3 * i], format!("user_{}", i)))
+            .collect();
+        let tree_params = TreeParams {
+            master_secret: random_secret(),
+            salt_s: Salt::generate_random(),
+            salt_b: Salt::generate_random(),
+        };
+        let mut tree_builder: TreeBuilder<Node, 3> =
+            TreeBuilder::new(records.clone(), Height::new(3), tree_params.clone());
+        let (tree, record_map) = tree_builder.build_single_threaded(None).unwrap();
+        let padding_fn = |pos: &NodePosition| tree_params.padding_node_content(pos);
+        let mut witness: MerkleWitness<Node, 3> = MerkleWitness::generate_witness(
+            records[1].hashed_email.clone(),
+            &tree,
+            &record_map,
+            &padding_fn,
+        )
+        .unwrap();
+        witness.attach_range_proofs().unwrap();
+        (witness.to_partial(), tree.root.to_partial())
+    }
+
+    fn encode(witness: &MerkleWitness<PartialNode, 3>) -> Proof {
+        Proof {
+            path: witness.path.0.iter().cloned().map(Into::into).collect(),
+            lefts: witness.lefts.clone(),
+            root: Some(witness.root.clone().into()),
+            user_node: Some(witness.user_leaf.clone().into()),
+            for_user: String::new(),
+            user_salt: vec![],
+            blinding_factor: vec![],
+            version: 0,
+            range_proofs: witness.range_proofs.iter().map(Into::into).collect(),
+            commitments: CommitmentScheme::Total as i32,
+        }
+    }
+
+    #[test]
+    fn verifies_witness() {
+        let (witness, root) = witness();
+        witness.verify(&root).unwrap();
+
+        let other_root = witness.path.0[0].clone();
+        assert!(matches!(
+            witness.verify(&other_root),
+            Err(ErrorKind::ProofRootMismatch)
+        ));
+    }
+
+    #[test]
+    fn verifies_encoded_proof() {
+        let (witness, root) = witness();
+        let proof = encode(&witness);
+        verify_encoded_proof(&proof.encode_to_vec(), &root).unwrap();
+
+        let mut short_lefts = proof.clone();
+        short_lefts.lefts.pop();
+        assert!(matches!(
+            verify_encoded_proof(&short_lefts.encode_to_vec(), &root),
+            Err(ErrorKind::ProofLeftsMismatch { .. })
+        ));
+
+        let mut bad_point = proof.clone();
+        bad_point.path[0].commitment[0] ^= 1;
+        assert!(matches!(
+            verify_encoded_proof(&bad_point.encode_to_vec(), &root),
+            Err(ErrorKind::ProofMalformedCurvePoint)
+        ));
+
+        let mut empty_path = proof.clone();
+        empty_path.path.clear();
+        empty_path.lefts.clear();
+        assert!(matches!(
+            verify_encoded_proof(&empty_path.encode_to_vec(), &root),
+            Err(ErrorKind::ProofWrongPathLength { .. })
+        ));
+
+        let mut per_asset = proof.clone();
+        per_asset.commitments = CommitmentScheme::PerAsset as i32;
+        assert!(matches!(
+            verify_encoded_proof(&per_asset.encode_to_vec(), &root),
+            Err(ErrorKind::ProofSchemeMismatch { .. })
+        ));
+
+        let mut no_range_proofs = proof.clone();
+        no_range_proofs.range_proofs.clear();
+        assert!(matches!(
+            verify_encoded_proof(&no_range_proofs.encode_to_vec(), &root),
+            Err(ErrorKind::RangeProofMissing { .. })
+        ));
+
+        let mut swapped = proof;
+        swapped.range_proofs.swap(0, 1);
+        assert!(matches!(
+            verify_encoded_proof(&swapped.encode_to_vec(), &root),
+            Err(ErrorKind::RangeProofInvalid)
+        ));
+    }
+
+    #[test]
+    fn verifies_range_proofs() {
+        // random balances would overflow 64 bits once summed
+        let records: Vec<Record<3>> = (1..=3u64)
+            .map(|i| Record::new(&[i, 2 * i, 3 * i], format!("user_{}", i)))
+            .collect();
+        let tree_params = TreeParams {
+            master_secret: random_secret(),
+            salt_s: Salt::generate_random(),
+            salt_b: Salt::generate_random(),
+        };
+        let mut tree_builder: TreeBuilder<Node, 3> =
+            TreeBuilder::new(records.clone(), Height::new(2), tree_params.clone());
+        let (tree, record_map) = tree_builder.build_single_threaded(None).unwrap();
+        let padding_fn = |pos: &NodePosition| tree_params.padding_node_content(pos);
+        let mut witness: MerkleWitness<Node, 3> = MerkleWitness::generate_witness(
+            records[0].hashed_email.clone(),
+            &tree,
+            &record_map,
+            &padding_fn,
+        )
+        .unwrap();
+        assert!(matches!(
+            witness.verify_range_proofs(),
+            Err(ErrorKind::RangeProofMissing { .. })
+        ));
+        witness.attach_range_proofs().unwrap();
+        witness.verify_range_proofs().unwrap();
+
+        let partial = witness.to_partial();
+        partial.verify(&tree.root.to_partial()).unwrap();
+        partial.verify_range_proofs().unwrap();
+
+        let mut swapped = partial;
+        swapped.range_proofs.swap(0, 1);
+        assert!(matches!(
+            swapped.verify_range_proofs(),
+            Err(ErrorKind::RangeProofInvalid)
+        ));
+    }
+
+    #[test]
+    fn saved_witness_round_trip() {
+        let (witness, root) = witness();
+        let path = std::env::temp_dir().join("oram_smst_witness_round_trip.json");
+        let path = path.to_str().unwrap();
+        witness.save(Some(path)).unwrap();
+
+        let loaded: MerkleWitness<PartialNode, 3> = MerkleWitness::load(path).unwrap();
+        assert_eq!(loaded.user_leaf, witness.user_leaf);
+        assert_eq!(loaded.lefts, witness.lefts);
+        loaded.verify(&root).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,27 +1,358 @@
-use kimchi::{loc, snarky::api::SnarkyCircuit, FieldVar};
-use mina_curves::pasta::Pallas;
-use poly_commitment::ipa::OpeningProof;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{BigInteger, Field, One, PrimeField};
+use kimchi::{
+    groupmap::GroupMap,
+    loc,
+    proof::ProverProof,
+    snarky::api::{ProverIndexWrapper, SnarkyCircuit, VerifierIndexWrapper},
+    FieldVar,
+};
+use mina_curves::pasta::{Vesta, VestaParameters};
+use mina_poseidon::{
+    constants::PlonkSpongeConstantsKimchi,
+    sponge::{DefaultFqSponge, DefaultFrSponge},
+};
+use num_bigint::BigUint;
+use poly_commitment::{commitment::CommitmentCurve, ipa::OpeningProof};
 
-use crate::ScalarField;
+use crate::{
+    error::{ErrorKind, Result},
+    hasher::commitment_to_fields,
+    pedersen::{hash_to_curve, Pedersen},
+    BaseField, CurvePoint, ScalarField,
+};
 
-struct RangeCheckCircuit {}
+type BaseSponge = DefaultFqSponge<VestaParameters, PlonkSpongeConstantsKimchi>;
+type ScalarSponge = DefaultFrSponge<BaseField, PlonkSpongeConstantsKimchi>;
+
+pub type RangeCheckProof = ProverProof<Vesta, OpeningProof<Vesta>>;
+
+/// Domain of the offset point the double and add of the circuit starts from
+pub const OFFSET_DOMAIN: &[u8] = b"oram-smst range check offset";
+
+/// Bits of the liability, the liability is their weighted sum so it is below `2^64`
+const VALUE_BITS: usize = 64;
+
+/// A point as its affine coordinates in the circuit
+type PointVar = (FieldVar<BaseField>, FieldVar<BaseField>);
+
+/// Proves the pedersen commitment `C = l * G + b * H` given as public input opens to `l < 2^64`
+/// the circuit is over the base field of pallas so the coordinates of `C` are native, `l` and `b`
+/// are decomposed in bits and `T + l * G + b * H` is recomputed with a double and add over the
+/// constant multiples `2^i * G` and `2^i * H`, which must equal `T + C` for the offset `T`
+/// the offset keeps the incomplete additions away from the point at infinity
+pub struct RangeCheckCircuit {
+    /// `2^i * G` for every bit of the liability followed by `2^i * H` for every bit of the blinding
+    generators: Vec<(BaseField, BaseField)>,
+    offset: (BaseField, BaseField),
+}
+
+/// The native trace of the double and add, the hints the witness of the circuit is computed from
+pub struct RangeCheckWitness {
+    steps: Vec<Step>,
+    /// the slope and the sum of `C + T`
+    commitment_sum: (BaseField, (BaseField, BaseField)),
+}
+
+struct Step {
+    bit: bool,
+    lambda: BaseField,
+    sum: (BaseField, BaseField),
+    next: (BaseField, BaseField),
+}
+
+impl RangeCheckCircuit {
+    pub fn new() -> Self {
+        let pedersen = Pedersen::get();
+        let blinding_bits = ScalarField::MODULUS_BIT_SIZE as usize;
+        let doublings = |base: CurvePoint, n: usize| {
+            std::iter::successors(Some(base.into_group()), |point| Some(*point + point))
+                .take(n)
+                .map(|point| coordinates(&point.into_affine()))
+                .collect::<Vec<_>>()
+        };
+        let mut generators = doublings(pedersen.base, VALUE_BITS);
+        generators.extend(doublings(pedersen.base_blinding, blinding_bits));
+        Self {
+            generators,
+            offset: coordinates(&hash_to_curve(OFFSET_DOMAIN, &[])),
+        }
+    }
+
+    /// the native double and add for the opening `(liability, blinding)` of `commitment`
+    fn witness(
+        &self,
+        liability: u64,
+        blinding: ScalarField,
+        commitment: &CurvePoint,
+    ) -> Result<RangeCheckWitness> {
+        let value_bits = (0..VALUE_BITS).map(|i| (liability >> i) & 1 == 1);
+        let blinding_bits = blinding.into_bigint().to_bits_le();
+        let bits = value_bits.chain(
+            blinding_bits
+                .into_iter()
+                .take(self.generators.len() - VALUE_BITS),
+        );
+
+        let mut acc = self.offset;
+        let mut steps = Vec::with_capacity(self.generators.len());
+        for (bit, generator) in bits.zip(&self.generators) {
+            let (lambda, sum) = add(acc, *generator)?;
+            let next = if bit { sum } else { acc };
+            steps.push(Step {
+                bit,
+                lambda,
+                sum,
+                next,
+            });
+            acc = next;
+        }
+        let commitment_sum = add(coordinates(commitment), self.offset)?;
+        Ok(RangeCheckWitness {
+            steps,
+            commitment_sum,
+        })
+    }
+}
+
+impl Default for RangeCheckCircuit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl SnarkyCircuit for RangeCheckCircuit {
-    type Curve = Pallas;
+    type Curve = Vesta;
 
-    type PrivateInput = ScalarField;
-    type PublicInput = ();
+    type PrivateInput = RangeCheckWitness;
+    type PublicInput = PointVar;
     type PublicOutput = ();
     type Proof = OpeningProof<Self::Curve>;
 
     fn circuit(
         &self,
-        sys: &mut kimchi::RunState<ScalarField>,
-        _: Self::PublicInput,
-        private_input: Option<&Self::PrivateInput>,
+        sys: &mut kimchi::RunState<BaseField>,
+        commitment: Self::PublicInput,
+        witness: Option<&Self::PrivateInput>,
     ) -> kimchi::SnarkyResult<Self::PublicOutput> {
-        let a: FieldVar<ScalarField> = sys.compute(loc!(), |_| *(private_input.unwrap()))?;
-        sys.range_check(loc!(), a.clone(), a.clone(), a)?;
+        let one = FieldVar::constant(BaseField::one());
+        let mut acc = (
+            FieldVar::constant(self.offset.0),
+            FieldVar::constant(self.offset.1),
+        );
+        for (i, generator) in self.generators.iter().enumerate() {
+            let step = witness.map(|witness| &witness.steps[i]);
+
+            let bit: FieldVar<BaseField> =
+                sys.compute(loc!(), |_| BaseField::from(step.unwrap().bit))?;
+            sys.assert_r1cs(
+                Some("boolean bit"),
+                loc!(),
+                bit.clone(),
+                bit.clone(),
+                bit.clone(),
+            )?;
+
+            let hint = step.map(|step| (step.lambda, step.sum));
+            let sum = add_constant(sys, &acc, *generator, hint)?;
+
+            // `next = acc + bit * (sum - acc)` for both coordinates
+            let next_x: FieldVar<BaseField> = sys.compute(loc!(), |_| step.unwrap().next.0)?;
+            let next_y: FieldVar<BaseField> = sys.compute(loc!(), |_| step.unwrap().next.1)?;
+            sys.assert_r1cs(
+                Some("select x"),
+                loc!(),
+                bit.clone(),
+                sum.0 - acc.0.clone(),
+                next_x.clone() - acc.0,
+            )?;
+            sys.assert_r1cs(
+                Some("select y"),
+                loc!(),
+                bit,
+                sum.1 - acc.1.clone(),
+                next_y.clone() - acc.1,
+            )?;
+            acc = (next_x, next_y);
+        }
+
+        let hint = witness.map(|witness| witness.commitment_sum);
+        let commitment_sum = add_constant(sys, &commitment, self.offset, hint)?;
+        sys.assert_r1cs(
+            Some("commitment x"),
+            loc!(),
+            acc.0,
+            one.clone(),
+            commitment_sum.0,
+        )?;
+        sys.assert_r1cs(Some("commitment y"), loc!(), acc.1, one, commitment_sum.1)?;
         Ok(())
     }
 }
+
+/// `p + q` for a point `p` of the circuit and a constant `q` with the incomplete addition
+/// `lambda * (x_q - x_p) = y_q - y_p`, `lambda^2 = x + x_p + x_q`, `lambda * (x_p - x) = y + y_p`
+/// the slope and the sum are taken from `hint`, which is only read when proving
+fn add_constant(
+    sys: &mut kimchi::RunState<BaseField>,
+    p: &PointVar,
+    q: (BaseField, BaseField),
+    hint: Option<(BaseField, (BaseField, BaseField))>,
+) -> kimchi::SnarkyResult<PointVar> {
+    let (x_q, y_q) = (FieldVar::constant(q.0), FieldVar::constant(q.1));
+    let lambda: FieldVar<BaseField> = sys.compute(loc!(), |_| hint.unwrap().0)?;
+    let x: FieldVar<BaseField> = sys.compute(loc!(), |_| hint.unwrap().1 .0)?;
+    let y: FieldVar<BaseField> = sys.compute(loc!(), |_| hint.unwrap().1 .1)?;
+    sys.assert_r1cs(
+        Some("slope"),
+        loc!(),
+        lambda.clone(),
+        x_q.clone() - p.0.clone(),
+        y_q - p.1.clone(),
+    )?;
+    sys.assert_r1cs(
+        Some("sum x"),
+        loc!(),
+        lambda.clone(),
+        lambda.clone(),
+        x.clone() + p.0.clone() + x_q,
+    )?;
+    sys.assert_r1cs(
+        Some("sum y"),
+        loc!(),
+        lambda,
+        p.0.clone() - x.clone(),
+        y.clone() + p.1.clone(),
+    )?;
+    Ok((x, y))
+}
+
+/// the native incomplete addition, the slope and the sum of `p + q`
+fn add(
+    p: (BaseField, BaseField),
+    q: (BaseField, BaseField),
+) -> Result<(BaseField, (BaseField, BaseField))> {
+    let dx = q.0 - p.0;
+    let inverse = dx.inverse().ok_or(ErrorKind::RangeCheckCircuit(
+        "the double and add reached an exceptional case".to_string(),
+    ))?;
+    let lambda = (q.1 - p.1) * inverse;
+    let x = lambda.square() - p.0 - q.0;
+    let y = lambda * (p.0 - x) - p.1;
+    Ok((lambda, (x, y)))
+}
+
+fn coordinates(point: &CurvePoint) -> (BaseField, BaseField) {
+    let [x, y] = commitment_to_fields(point);
+    (x, y)
+}
+
+/// The compiled range check circuit, compiling is expensive so it is done once and reused
+pub struct RangeCheckProver {
+    circuit: RangeCheckCircuit,
+    prover_index: ProverIndexWrapper<RangeCheckCircuit>,
+    verifier_index: VerifierIndexWrapper<RangeCheckCircuit>,
+}
+
+impl RangeCheckProver {
+    pub fn compile() -> Result<Self> {
+        let (prover_index, verifier_index) = RangeCheckCircuit::new()
+            .compile_to_indexes()
+            .map_err(|err| ErrorKind::RangeCheckCircuit(err.to_string()))?;
+        Ok(Self {
+            circuit: RangeCheckCircuit::new(),
+            prover_index,
+            verifier_index,
+        })
+    }
+
+    /// proves `liability < 2^64` for the opening `(liability, blinding)` of `commitment`
+    /// the commitment is the public input of the proof, `verify` must be given the same one
+    pub fn prove(
+        &mut self,
+        liability: ScalarField,
+        blinding: ScalarField,
+        commitment: &CurvePoint,
+    ) -> Result<RangeCheckProof> {
+        if Pedersen::get().commit(liability, blinding) != *commitment {
+            return Err(ErrorKind::RangeCheckOpeningMismatch);
+        }
+        let liability = u64::try_from(BigUint::from(liability.into_bigint()))
+            .map_err(|_| ErrorKind::RangeProofValueOutOfRange)?;
+        let witness = self.circuit.witness(liability, blinding, commitment)?;
+        // debug makes the prover check the witness against the constraints
+        let (proof, _) = self
+            .prover_index
+            .prove::<BaseSponge, ScalarSponge>(coordinates(commitment), witness, true)
+            .map_err(|err| ErrorKind::RangeCheckCircuit(err.to_string()))?;
+        Ok(proof)
+    }
+
+    /// verifies the proof against the commitment it was made for
+    pub fn verify(&self, proof: &RangeCheckProof, commitment: &CurvePoint) -> Result<()> {
+        let group_map = <Vesta as CommitmentCurve>::Map::setup();
+        let public_input = commitment_to_fields(commitment);
+        // the verifier of kimchi reports an invalid proof, the snarky wrapper would panic
+        kimchi::verifier::verify::<Vesta, BaseSponge, ScalarSponge, OpeningProof<Vesta>>(
+            &group_map,
+            &self.verifier_index.index,
+            proof,
+            &public_input,
+        )
+        .map_err(|err| ErrorKind::RangeCheckProofInvalid(err.to_string()))
+    }
+}
+
+pub fn serialize_proof(proof: &RangeCheckProof) -> Result<Vec<u8>> {
+    serde_json::to_vec(proof).map_err(|err| ErrorKind::RangeCheckCircuit(err.to_string()))
+}
+
+pub fn deserialize_proof(bytes: &[u8]) -> Result<RangeCheckProof> {
+    serde_json::from_slice(bytes).map_err(|err| ErrorKind::ProofMalformed(err.to_string()))
+}
+
+/// the largest liability accepted by the circuit
+pub fn max_liability() -> ScalarField {
+    ScalarField::from(BigUint::from(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_ff::One;
+
+    use super::{deserialize_proof, max_liability, serialize_proof, RangeCheckProver};
+    use crate::{error::ErrorKind, pedersen::Pedersen, ScalarField};
+
+    #[test]
+    fn range_check_boundaries() {
+        let mut prover = RangeCheckProver::compile().unwrap();
+        let blinding = ScalarField::from(7u64);
+        for liability in [ScalarField::from(0u64), ScalarField::one(), max_liability()] {
+            let commitment = Pedersen::get().commit(liability, blinding);
+            let proof = prover.prove(liability, blinding, &commitment).unwrap();
+            let bytes = serialize_proof(&proof).unwrap();
+            let proof = deserialize_proof(&bytes).unwrap();
+            prover.verify(&proof, &commitment).unwrap();
+
+            // the proof is bound to the commitment it was made for
+            let other = Pedersen::get().commit(liability + ScalarField::one(), blinding);
+            assert!(matches!(
+                prover.verify(&proof, &other),
+                Err(ErrorKind::RangeCheckProofInvalid(_))
+            ));
+        }
+
+        let overflow = max_liability() + ScalarField::one();
+        let commitment = Pedersen::get().commit(overflow, blinding);
+        assert!(matches!(
+            prover.prove(overflow, blinding, &commitment),
+            Err(ErrorKind::RangeProofValueOutOfRange)
+        ));
+
+        let wrong_opening = Pedersen::get().commit(ScalarField::one(), blinding);
+        assert!(matches!(
+            prover.prove(ScalarField::from(2u64), blinding, &wrong_opening),
+            Err(ErrorKind::RangeCheckOpeningMismatch)
+        ));
+    }
+}
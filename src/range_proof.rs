@@ -0,0 +1,315 @@
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{Field, One, PrimeField, UniformRand, Zero};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::rand::Rng;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use sha2::{Digest, Sha256};
+use std::ops::Mul;
+
+use crate::{
+    error::{ErrorKind, Result},
+    pedersen::Pedersen,
+    smt, CurvePoint, ScalarField,
+};
+
+/// Number of bits the committed value is proven to fit in
+pub const RANGE_BITS: usize = 64;
+
+const CHALLENGE_DOMAIN: &[u8] = b"oram-smst range proof";
+
+type Projective = <CurvePoint as AffineRepr>::Group;
+
+/// A node exposing its commitment
+pub trait Committed {
+    fn commitment(&self) -> CurvePoint;
+}
+
+/// A node that also knows the opening of its commitment, only available to the prover
+pub trait Opened: Committed {
+    fn liability(&self) -> &BigUint;
+    fn blinding_factor(&self) -> ScalarField;
+}
+
+/// Proof that `C_i = b * G + r_i * H` commits to a bit `b`
+/// a Schnorr OR proof of knowledge of the discrete log of `C_i` or `C_i - G` relative to `H`
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BitProof {
+    #[serde_as(as = "crate::serialize::SerdeAs")]
+    commitment: CurvePoint,
+    #[serde_as(as = "crate::serialize::SerdeAs")]
+    a0: CurvePoint,
+    #[serde_as(as = "crate::serialize::SerdeAs")]
+    a1: CurvePoint,
+    /// challenge of the zero branch, the one branch uses `e - e0`
+    #[serde_as(as = "crate::serialize::SerdeAs")]
+    e0: ScalarField,
+    #[serde_as(as = "crate::serialize::SerdeAs")]
+    z0: ScalarField,
+    #[serde_as(as = "crate::serialize::SerdeAs")]
+    z1: ScalarField,
+}
+
+/// Proof that a pedersen commitment opens to a value in `[0, 2^64)`
+/// the value is decomposed in bit commitments `C_i` such that `sum(2^i * C_i) = C`
+/// and every `C_i` is proven to commit to either 0 or 1
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RangeProof {
+    bits: Vec<BitProof>,
+}
+
+impl RangeProof {
+    /// proves the commitment `value * G + blinding * H` is in range
+    pub fn prove<R: Rng>(value: u64, blinding: ScalarField, rng: &mut R) -> Self {
+        let pedersen = Pedersen::get();
+        let commitment = pedersen.commit(value.into(), blinding);
+
+        // bit blinding factors such that `sum(2^i * r_i) = blinding`
+        let mut bit_blindings = Vec::with_capacity(RANGE_BITS);
+        let mut weighted_sum = ScalarField::zero();
+        let mut power = ScalarField::one();
+        for i in 0..RANGE_BITS {
+            let r_i = if i < RANGE_BITS - 1 {
+                ScalarField::rand(rng)
+            } else {
+                (blinding - weighted_sum) * power.inverse().unwrap()
+            };
+            weighted_sum += power * r_i;
+            power.double_in_place();
+            bit_blindings.push(r_i);
+        }
+
+        let bits = bit_blindings
+            .into_iter()
+            .enumerate()
+            .map(|(i, r_i)| {
+                let bit = (value >> i) & 1 == 1;
+                prove_bit(pedersen, &commitment, i, bit, r_i, rng)
+            })
+            .collect();
+        RangeProof { bits }
+    }
+
+    /// proves the commitment of a node with a known opening
+    pub fn for_node<N: Opened, R: Rng>(node: &N, rng: &mut R) -> Result<Self> {
+        let value =
+            u64::try_from(node.liability()).map_err(|_| ErrorKind::RangeProofValueOutOfRange)?;
+        Ok(Self::prove(value, node.blinding_factor(), rng))
+    }
+
+    pub fn verify(&self, commitment: &CurvePoint) -> Result<()> {
+        if self.bits.len() != RANGE_BITS {
+            return Err(ErrorKind::RangeProofInvalid);
+        }
+        let pedersen = Pedersen::get();
+        let mut sum = Projective::zero();
+        let mut power = ScalarField::one();
+        for (i, bit) in self.bits.iter().enumerate() {
+            if !verify_bit(pedersen, commitment, i, bit) {
+                return Err(ErrorKind::RangeProofInvalid);
+            }
+            sum += bit.commitment.mul(power);
+            power.double_in_place();
+        }
+        if sum.into_affine() != *commitment {
+            return Err(ErrorKind::RangeProofInvalid);
+        }
+        Ok(())
+    }
+}
+
+impl From<&RangeProof> for smt::RangeProof {
+    fn from(proof: &RangeProof) -> Self {
+        smt::RangeProof {
+            bits: proof
+                .bits
+                .iter()
+                .map(|bit| smt::BitProof {
+                    commitment: encode(&bit.commitment),
+                    a0: encode(&bit.a0),
+                    a1: encode(&bit.a1),
+                    e0: encode(&bit.e0),
+                    z0: encode(&bit.z0),
+                    z1: encode(&bit.z1),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<smt::RangeProof> for RangeProof {
+    type Error = ErrorKind;
+
+    /// the points must be in the prime order subgroup and every value must use all of its bytes
+    fn try_from(proof: smt::RangeProof) -> Result<Self> {
+        let bits = proof
+            .bits
+            .into_iter()
+            .map(|bit| {
+                Ok(BitProof {
+                    commitment: decode(&bit.commitment)?,
+                    a0: decode(&bit.a0)?,
+                    a1: decode(&bit.a1)?,
+                    e0: decode(&bit.e0)?,
+                    z0: decode(&bit.z0)?,
+                    z1: decode(&bit.z1)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RangeProof { bits })
+    }
+}
+
+fn encode<T: CanonicalSerialize>(value: &T) -> Vec<u8> {
+    let mut bytes = vec![];
+    value.serialize_uncompressed(&mut bytes).unwrap();
+    bytes
+}
+
+fn decode<T: CanonicalDeserialize>(bytes: &[u8]) -> Result<T> {
+    let mut reader = bytes;
+    let value =
+        T::deserialize_uncompressed(&mut reader).map_err(|_| ErrorKind::RangeProofMalformed)?;
+    if !reader.is_empty() {
+        return Err(ErrorKind::RangeProofMalformed);
+    }
+    Ok(value)
+}
+
+fn prove_bit<R: Rng>(
+    pedersen: &Pedersen,
+    commitment: &CurvePoint,
+    index: usize,
+    bit: bool,
+    blinding: ScalarField,
+    rng: &mut R,
+) -> BitProof {
+    let h = pedersen.base_blinding;
+    let bit_commitment = pedersen.commit(u64::from(bit).into(), blinding);
+    let p0 = bit_commitment.into_group();
+    let p1 = p0 - pedersen.base;
+
+    // the branch for the other bit is simulated with a chosen challenge and response
+    let k = ScalarField::rand(rng);
+    let fake_e = ScalarField::rand(rng);
+    let fake_z = ScalarField::rand(rng);
+    let (a0, a1) = if bit {
+        (h.mul(fake_z) - p0.mul(fake_e), h.mul(k))
+    } else {
+        (h.mul(k), h.mul(fake_z) - p1.mul(fake_e))
+    };
+    let (a0, a1) = (a0.into_affine(), a1.into_affine());
+    let e = challenge(commitment, index, &bit_commitment, &a0, &a1);
+    let real_e = e - fake_e;
+    let real_z = k + real_e * blinding;
+    let (e0, z0, z1) = if bit {
+        (fake_e, fake_z, real_z)
+    } else {
+        (real_e, real_z, fake_z)
+    };
+    BitProof {
+        commitment: bit_commitment,
+        a0,
+        a1,
+        e0,
+        z0,
+        z1,
+    }
+}
+
+fn verify_bit(pedersen: &Pedersen, commitment: &CurvePoint, index: usize, bit: &BitProof) -> bool {
+    let h = pedersen.base_blinding;
+    let p0 = bit.commitment.into_group();
+    let p1 = p0 - pedersen.base;
+    let e = challenge(commitment, index, &bit.commitment, &bit.a0, &bit.a1);
+    let e1 = e - bit.e0;
+    h.mul(bit.z0) == p0.mul(bit.e0) + bit.a0 && h.mul(bit.z1) == p1.mul(e1) + bit.a1
+}
+
+/// fiat shamir challenge bound to the node commitment and the bit index
+fn challenge(
+    commitment: &CurvePoint,
+    index: usize,
+    bit_commitment: &CurvePoint,
+    a0: &CurvePoint,
+    a1: &CurvePoint,
+) -> ScalarField {
+    let mut bytes = CHALLENGE_DOMAIN.to_vec();
+    for point in [commitment, bit_commitment, a0, a1] {
+        point.serialize_compressed(&mut bytes).unwrap();
+    }
+    bytes.extend_from_slice(&(index as u64).to_le_bytes());
+    ScalarField::from_le_bytes_mod_order(&Sha256::digest(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_ff::UniformRand;
+    use ark_std::rand::thread_rng;
+
+    use super::RangeProof;
+    use crate::{
+        error::ErrorKind,
+        nodes::{node::Node, TreeNode},
+        pedersen::Pedersen,
+        record::Record,
+        secret::Secret,
+        smt, ScalarField,
+    };
+
+    #[test]
+    fn proves_values_in_range() {
+        let mut rng = thread_rng();
+        for value in [0, 1, 2, 1 << 32, u64::MAX - 1, u64::MAX] {
+            let blinding = ScalarField::rand(&mut rng);
+            let commitment = Pedersen::get().commit(value.into(), blinding);
+            let proof = RangeProof::prove(value, blinding, &mut rng);
+            proof.verify(&commitment).unwrap();
+
+            let other = Pedersen::get().commit((value ^ 1).into(), blinding);
+            assert!(matches!(
+                proof.verify(&other),
+                Err(ErrorKind::RangeProofInvalid)
+            ));
+        }
+    }
+
+    #[test]
+    fn encoded_round_trip() {
+        let mut rng = thread_rng();
+        let blinding = ScalarField::rand(&mut rng);
+        let commitment = Pedersen::get().commit(42u64.into(), blinding);
+        let proof = RangeProof::prove(42, blinding, &mut rng);
+
+        let encoded = smt::RangeProof::from(&proof);
+        let decoded = RangeProof::try_from(encoded.clone()).unwrap();
+        assert_eq!(decoded, proof);
+        decoded.verify(&commitment).unwrap();
+
+        let mut trailing = encoded.clone();
+        trailing.bits[0].z0.push(0);
+        assert!(matches!(
+            RangeProof::try_from(trailing),
+            Err(ErrorKind::RangeProofMalformed)
+        ));
+
+        let mut not_on_curve = encoded;
+        not_on_curve.bits[0].commitment[0] ^= 1;
+        assert!(matches!(
+            RangeProof::try_from(not_on_curve),
+            Err(ErrorKind::RangeProofMalformed)
+        ));
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        let record = Record::new(&[u64::MAX, 1], String::from("S"));
+        let leaf = Node::new_leaf(Secret::from(2u32), &record, Secret::from(1u32));
+        assert!(matches!(
+            RangeProof::for_node(&leaf, &mut thread_rng()),
+            Err(ErrorKind::RangeProofValueOutOfRange)
+        ));
+    }
+}
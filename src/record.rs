@@ -20,6 +20,10 @@ impl<const N_CURR: usize> Record<N_CURR> {
             .sum()
     }
 
+    pub fn balances(&self) -> &[u64; N_CURR] {
+        &self.balances
+    }
+
     pub fn to_hashable(&self) -> Hashables {
         Hashables::Id(self.hashed_email.clone())
     }
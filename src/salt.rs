@@ -1,12 +1,12 @@
-use ark_std::rand::{
-    distributions::{Alphanumeric, DistString},
-    thread_rng,
-};
-use std::str::FromStr;
+use rand::Rng;
+use std::{fmt::Debug, str::FromStr};
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use crate::error::ErrorKind;
 
-#[derive(Clone, Debug)]
+/// A 256-bit salt wiped on drop, compared in constant time and never printed
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
 pub struct Salt([u8; 32]);
 
 impl Salt {
@@ -14,10 +14,9 @@ impl Salt {
         self.0
     }
 
+    /// a full 256-bit salt from the thread local CSPRNG
     pub fn generate_random() -> Self {
-        let mut rng = thread_rng();
-        let random_str = Alphanumeric.sample_string(&mut rng, 32);
-        Self::from_str(&random_str).unwrap()
+        Salt(rand::rng().random())
     }
 
     pub fn as_hex(&self) -> String {
@@ -25,6 +24,26 @@ impl Salt {
     }
 }
 
+impl Debug for Salt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Salt(<REDACTED>)")
+    }
+}
+
+impl ConstantTimeEq for Salt {
+    fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl PartialEq for Salt {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for Salt {}
+
 impl FromStr for Salt {
     type Err = ErrorKind;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -1,11 +1,14 @@
-use std::str::FromStr;
+use std::{fmt::Debug, str::FromStr};
 
-use crate::{error::ErrorKind, ScalarField};
+use crate::{error::ErrorKind, kdf, ScalarField};
 use num_bigint::BigUint;
 use rand::Rng;
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 /// A 256-bit packet for the pedersen commitment
-#[derive(Clone, Debug, Copy)]
+/// wiped on drop, compared in constant time and never printed
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
 pub struct Secret([u8; 32]);
 
 impl Secret {
@@ -13,13 +16,14 @@ impl Secret {
         &self.0
     }
 
+    /// the bytes read as a little-endian integer
     pub fn to_bigint(&self) -> BigUint {
         BigUint::from_bytes_le(self.as_bytes_slice())
     }
 
+    /// the scalar derived from the secret with `kdf::hash_to_scalar`, used for blinding factors
     pub fn to_field(&self) -> ScalarField {
-        let biguint = BigUint::from_bytes_be(&self.0);
-        ScalarField::from(biguint)
+        kdf::hash_to_scalar(&self.0)
     }
 
     pub fn as_vec(&self) -> Vec<u8> {
@@ -27,6 +31,26 @@ impl Secret {
     }
 }
 
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret(<REDACTED>)")
+    }
+}
+
+impl ConstantTimeEq for Secret {
+    fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl PartialEq for Secret {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for Secret {}
+
 impl From<u32> for Secret {
     fn from(value: u32) -> Self {
         let u32_bytes = value.to_be_bytes();
@@ -58,6 +82,22 @@ impl FromStr for Secret {
     }
 }
 
+/// a full 256-bit secret from the thread local CSPRNG
 pub fn random_secret() -> Secret {
-    Secret::from(rand::rng().random::<u32>())
+    Secret(rand::rng().random())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{random_secret, Secret};
+
+    #[test]
+    fn secrets_are_random_and_redacted() {
+        let secret = random_secret();
+        assert_ne!(secret, random_secret());
+        assert_eq!(secret.clone(), secret);
+        // a u32 would leave at least 28 zero bytes
+        assert!(secret.as_bytes_slice().iter().filter(|&&b| b == 0).count() < 28);
+        assert_eq!(format!("{:?}", Secret::from(7u32)), "Secret(<REDACTED>)");
+    }
 }
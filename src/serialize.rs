@@ -1,4 +1,4 @@
-use ark_serialize::CanonicalSerialize;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use serde_with::Bytes;
 use std::fmt::Debug;
 
@@ -17,3 +17,57 @@ impl<T: CanonicalSerialize + Debug> serde_with::SerializeAs<T> for SerdeAs {
         Bytes::serialize_as(&bytes, serializer)
     }
 }
+
+/// the bytes are deserialized with validation so points not on the curve or outside the subgroup are rejected
+/// trailing bytes after the value are rejected as well, like `TryFrom<NodeContent>` does
+impl<'de, T: CanonicalDeserialize> serde_with::DeserializeAs<'de, T> for SerdeAs {
+    fn deserialize_as<D>(deserializer: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let bytes: Vec<u8> = Bytes::deserialize_as(deserializer)?;
+        let mut reader = bytes.as_slice();
+        let value = T::deserialize_uncompressed(&mut reader).map_err(serde::de::Error::custom)?;
+        if !reader.is_empty() {
+            return Err(serde::de::Error::custom(format!(
+                "{} trailing bytes after the value",
+                reader.len()
+            )));
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_ec::AffineRepr;
+    use serde::{Deserialize, Serialize};
+    use serde_with::serde_as;
+
+    use crate::{CurvePoint, ScalarField};
+
+    #[serde_as]
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Wrapped {
+        #[serde_as(as = "super::SerdeAs")]
+        point: CurvePoint,
+        #[serde_as(as = "super::SerdeAs")]
+        scalar: ScalarField,
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let wrapped = Wrapped {
+            point: CurvePoint::generator(),
+            scalar: ScalarField::from(7u32),
+        };
+        let mut json: serde_json::Value = serde_json::to_value(&wrapped).unwrap();
+        assert_eq!(
+            serde_json::from_value::<Wrapped>(json.clone()).unwrap(),
+            wrapped
+        );
+
+        json["point"].as_array_mut().unwrap().push(0.into());
+        assert!(serde_json::from_value::<Wrapped>(json).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::fmt::Debug;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     error::{ErrorKind, Result},
@@ -10,7 +10,7 @@ use crate::{
     tree_builder::{single::single_threaded_tree_builder, PaddingNodeContent},
 };
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Siblings<T: TreeNode + Clone + Debug + Serialize>(pub Vec<T>);
 
 impl<T: TreeNode + Clone + Debug + Serialize> Siblings<T> {
@@ -33,8 +33,8 @@ impl<T: TreeNode + Clone + Debug + Serialize> Siblings<T> {
             let sibling = match tree.store.get_node(&siblings_pos) {
                 Some(node) => node,
                 None => {
-                    // if at zero level we do not find node it was a padding node
-                    if y == 0 {
+                    // a missing node at a stored level has no leaves below it so it is a padding node
+                    if y <= tree.store_depth {
                         let padding_node_content = padding_node_content(&siblings_pos);
                         T::new_pad(padding_node_content, siblings_pos)
                     } else {
@@ -73,15 +73,23 @@ impl<T: TreeNode + Clone + Debug + Serialize> Siblings<T> {
     }
 
     /// generates the root from the given path
-    pub fn get_root_from_path(&self, leaf_node: T, lefts: &Vec<bool>) -> T {
+    /// the positions of the ancestors are recovered from `lefts`, the sibling at level `i`
+    /// is on the left exactly when bit `i` of the leaf `x` cordinate is set
+    pub fn get_root_from_path(&self, leaf_node: T, lefts: &[bool]) -> T {
+        let x_cord = lefts
+            .iter()
+            .enumerate()
+            .fold(0u64, |x, (i, left)| x | (u64::from(*left) << i));
         let mut root = leaf_node;
+        let mut position = NodePosition::new(x_cord, Height::new(0));
         for (node, left) in self.0.iter().zip(lefts) {
+            position = position.get_parent_node_pos();
             match left {
                 true => {
-                    root = T::merge(node, &root);
+                    root = T::merge(node, &root, &position);
                 }
                 false => {
-                    root = T::merge(&root, node);
+                    root = T::merge(&root, node, &position);
                 }
             }
         }
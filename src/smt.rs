@@ -36,8 +36,46 @@ pub struct Proof {
     pub user_node: ::core::option::Option<NodeContent>,
     #[prost(string, tag = "5")]
     pub for_user: ::prost::alloc::string::String,
+    /// the salt `s` of the user leaf needed to recompute its hash
+    #[prost(bytes = "vec", tag = "7")]
+    pub user_salt: ::prost::alloc::vec::Vec<u8>,
+    /// the blinding factor of the user leaf needed to open its commitment `C = l * G + b * H`,
+    /// the scalar `b` of the pallas curve as 32 uncompressed little endian bytes
+    #[prost(bytes = "vec", tag = "8")]
+    pub blinding_factor: ::prost::alloc::vec::Vec<u8>,
+    /// the version of the proof protocol
+    #[prost(uint32, tag = "9")]
+    pub version: u32,
+    /// the range proofs of the user node followed by every node of the path
+    #[prost(message, repeated, tag = "10")]
+    pub range_proofs: ::prost::alloc::vec::Vec<RangeProof>,
+    /// what the commitments of the nodes open to, the range proofs are only sent for `TOTAL`
+    #[prost(enumeration = "CommitmentScheme", tag = "11")]
+    pub commitments: i32,
+}
+/// *
+/// Proof that a commitment opens to a value in `\[0, 2^64)`, one bit proof per bit of the value
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RangeProof {
+    #[prost(message, repeated, tag = "1")]
+    pub bits: ::prost::alloc::vec::Vec<BitProof>,
+}
+/// *
+/// Proof that the commitment of a bit opens to 0 or 1, the points and scalars are uncompressed
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BitProof {
+    #[prost(bytes = "vec", tag = "1")]
+    pub commitment: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub a0: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub a1: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub e0: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    pub z0: ::prost::alloc::vec::Vec<u8>,
     #[prost(bytes = "vec", tag = "6")]
-    pub master_salt: ::prost::alloc::vec::Vec<u8>,
+    pub z1: ::prost::alloc::vec::Vec<u8>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct SetRecordRequest {
@@ -51,6 +89,36 @@ pub struct Response {
     #[prost(string, tag = "1")]
     pub msg: ::prost::alloc::string::String,
 }
+/// *
+/// What the commitments of the nodes open to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum CommitmentScheme {
+    /// `C = l * G + b * H` with `l` the total liability of the node
+    Total = 0,
+    /// `C = sum(l_i * G_i) + b * H` with `l_i` the liability of the node in the asset `i`
+    PerAsset = 1,
+}
+impl CommitmentScheme {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Total => "TOTAL",
+            Self::PerAsset => "PER_ASSET",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "TOTAL" => Some(Self::Total),
+            "PER_ASSET" => Some(Self::PerAsset),
+            _ => None,
+        }
+    }
+}
 /// Generated client implementations.
 pub mod smt_backend_client {
     #![allow(
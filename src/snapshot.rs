@@ -0,0 +1,256 @@
+use std::{
+    fmt::Debug,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    error::{ErrorKind, Result},
+    node_position::{Height, NodePosition},
+    nodes::TreeNode,
+    store::Store,
+    tree::{RecordMap, TreeParams, SMT},
+    tree_builder::single::single_threaded_tree_builder,
+};
+
+/// Version of the on-disk snapshot format, bumped on every incompatible change
+/// 2 hashes the nodes with version 1 of the hashing scheme in `hasher`, 3 keeps the openings of
+/// the nodes built by `oram_smst build` so `prove` can attach range proofs
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+/// The on-disk representation of a built tree
+/// the tree params are not stored only their fingerprint to detect loading with other secrets
+#[derive(Serialize, Deserialize)]
+struct Snapshot<T> {
+    version: u32,
+    params_fingerprint: String,
+    height: Height,
+    root: T,
+    /// json maps only support string keys so the store is kept as a list
+    nodes: Vec<(NodePosition, T)>,
+    record_map: RecordMap,
+}
+
+/// writes the tree and the record map to `path`
+/// the file is written next to `path` first and then renamed so a crash never leaves a partial snapshot
+pub fn save<T, P>(
+    tree: &SMT<T>,
+    record_map: &RecordMap,
+    tree_params: &TreeParams,
+    path: P,
+) -> Result<()>
+where
+    T: TreeNode + Clone + Debug + Serialize,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        params_fingerprint: tree_params.fingerprint(),
+        height: tree.height,
+        root: &tree.root,
+        nodes: tree
+            .store
+            .map
+            .iter()
+            .map(|(pos, node)| (*pos, node))
+            .collect(),
+        record_map: record_map.clone(),
+    };
+
+    // appended so `tree.snap` and `tree.json` never share `tree.tmp`
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let file = File::create(&tmp_path).map_err(|err| ErrorKind::SnapshotIo(err.to_string()))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, &snapshot)
+        .map_err(|err| ErrorKind::SnapshotMalformed(err.to_string()))?;
+    let file = writer
+        .into_inner()
+        .map_err(|err| ErrorKind::SnapshotIo(err.to_string()))?;
+    file.sync_all()
+        .map_err(|err| ErrorKind::SnapshotIo(err.to_string()))?;
+    // the entry of the temp file and then the rename are both made durable
+    sync_parent(path)?;
+    fs::rename(&tmp_path, path).map_err(|err| ErrorKind::SnapshotIo(err.to_string()))?;
+    sync_parent(path)
+}
+
+/// fsyncs the directory holding `path` so a rename into it survives a crash
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|err| ErrorKind::SnapshotIo(err.to_string()))
+}
+
+/// directories cannot be opened for an fsync outside unix
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// loads a tree saved with `save`
+/// the root and every stored node are checked against a recomputation from the stored leaves
+pub fn load<T, P>(path: P, tree_params: &TreeParams) -> Result<(SMT<T>, RecordMap)>
+where
+    T: TreeNode + Clone + Debug + Serialize + DeserializeOwned + PartialEq,
+    P: AsRef<Path>,
+{
+    let file = File::open(path).map_err(|err| ErrorKind::SnapshotIo(err.to_string()))?;
+    let snapshot: Snapshot<T> = serde_json::from_reader(BufReader::new(file))
+        .map_err(|err| ErrorKind::SnapshotMalformed(err.to_string()))?;
+
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(ErrorKind::SnapshotVersionMismatch {
+            found: snapshot.version,
+            expected: SNAPSHOT_VERSION,
+        });
+    }
+    if snapshot.params_fingerprint != tree_params.fingerprint() {
+        return Err(ErrorKind::SnapshotParamsMismatch);
+    }
+
+    let store = Store {
+        map: snapshot.nodes.into_iter().collect(),
+    };
+    verify_integrity(&store, &snapshot.root, &snapshot.height, tree_params)?;
+    let store_depth = store_depth(&store);
+
+    Ok((
+        SMT {
+            root: snapshot.root,
+            store,
+            height: snapshot.height,
+            store_depth,
+        },
+        snapshot.record_map,
+    ))
+}
+
+fn verify_integrity<T>(
+    store: &Store<T>,
+    root: &T,
+    height: &Height,
+    tree_params: &TreeParams,
+) -> Result<()>
+where
+    T: TreeNode + Clone + Debug + Serialize + PartialEq,
+{
+    let mut leaf_nodes: Vec<(NodePosition, T)> = store
+        .map
+        .iter()
+        .filter(|(pos, _)| pos.1.as_u8() == 0)
+        .map(|(pos, node)| (*pos, node.clone()))
+        .collect();
+    if leaf_nodes.is_empty() {
+        return Err(ErrorKind::SnapshotIntegrity);
+    }
+    leaf_nodes.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+    let store_depth = store_depth(store);
+
+    let padding_fn = |pos: &NodePosition| tree_params.padding_node_content(pos);
+    let recomputed = single_threaded_tree_builder(leaf_nodes, height, store_depth, &padding_fn)?;
+    if recomputed.root != *root || recomputed.store.map != store.map {
+        return Err(ErrorKind::SnapshotIntegrity);
+    }
+    Ok(())
+}
+
+/// the highest level of the stored nodes
+fn store_depth<T: TreeNode + Clone + Debug>(store: &Store<T>) -> u8 {
+    store
+        .map
+        .keys()
+        .map(|pos| pos.1.as_u8())
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::ErrorKind,
+        node_position::{Height, NodePosition},
+        nodes::node::Node,
+        proofs::MerkleWitness,
+        record::{random_records, Record},
+        salt::Salt,
+        secret::random_secret,
+        tree::{TreeBuilder, TreeParams, SMT},
+    };
+
+    #[test]
+    fn snapshot_round_trip() {
+        let records = random_records::<3>(10);
+        let tree_params = TreeParams {
+            master_secret: random_secret(),
+            salt_s: Salt::generate_random(),
+            salt_b: Salt::generate_random(),
+        };
+        let mut tree_builder: TreeBuilder<Node, 3> =
+            TreeBuilder::new(records, Height::new(4), tree_params.clone());
+        let (tree, record_map) = tree_builder.build_single_threaded(Some(2)).unwrap();
+
+        let path = std::env::temp_dir().join("oram_smst_snapshot_round_trip.json");
+        super::save(&tree, &record_map, &tree_params, &path).unwrap();
+        assert!(!path.with_extension("json.tmp").exists());
+        let (loaded, loaded_record_map): (SMT<Node>, _) = super::load(&path, &tree_params).unwrap();
+        assert_eq!(loaded.root, tree.root);
+        assert_eq!(loaded.store.map, tree.store.map);
+        assert_eq!(loaded_record_map, record_map);
+
+        let other_params = TreeParams {
+            master_secret: random_secret(),
+            ..tree_params
+        };
+        let result: Result<(SMT<Node>, _), _> = super::load(&path, &other_params);
+        assert!(matches!(result, Err(ErrorKind::SnapshotParamsMismatch)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// the flow of `oram_smst prove` and `verify`, the loaded openings prove the ranges
+    #[test]
+    fn loaded_snapshot_proves_ranges() {
+        let records: Vec<Record<3>> = (1..=6u64)
+            .map(|i| Record::new(&[i, 2 * i, 3 * i], format!("user_{}", i)))
+            .collect();
+        let tree_params = TreeParams::demo();
+        let mut tree_builder: TreeBuilder<Node, 3> =
+            TreeBuilder::new(records.clone(), Height::new(3), tree_params.clone());
+        let (tree, record_map) = tree_builder.build_single_threaded(None).unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "oram_smst_snapshot_ranges_{}.json",
+            std::process::id()
+        ));
+        super::save(&tree, &record_map, &tree_params, &path).unwrap();
+
+        let (loaded, record_map): (SMT<Node>, _) = super::load(&path, &tree_params).unwrap();
+        let padding_fn = |pos: &NodePosition| tree_params.padding_node_content(pos);
+        let mut witness: MerkleWitness<Node, 3> = MerkleWitness::generate_witness(
+            records[2].hashed_email.clone(),
+            &loaded,
+            &record_map,
+            &padding_fn,
+        )
+        .unwrap();
+        let unproven = witness.to_partial();
+        assert!(matches!(
+            unproven.verify_range_proofs(),
+            Err(ErrorKind::RangeProofMissing { .. })
+        ));
+        witness.attach_range_proofs().unwrap();
+        let witness = witness.to_partial();
+        witness.verify(&tree.root.to_partial()).unwrap();
+        witness.verify_range_proofs().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,27 +1,97 @@
 use std::{collections::HashMap, fmt::Debug, marker::PhantomData};
 
 use crate::{
-    error::Result,
+    error::{ErrorKind, Result},
     kdf,
     node_position::{Height, NodePosition},
-    nodes::TreeNode,
+    nodes::{partial::PartialNode, ServedNode, TreeNode},
     record::Record,
     salt::Salt,
     secret::Secret,
+    siblings::Siblings,
     store::Store,
     tree_builder::PaddingNodeContent,
 };
-use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use rayon::prelude::*;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 
 /// A map for the user string to the NodePosition
 pub(crate) type RecordMap = HashMap<String, NodePosition>;
+
+/// Number of leaves whose commitments are batched together on one thread
+const LEAF_BATCH_SIZE: usize = 1024;
+
 #[derive(Debug)]
 pub struct SMT<T: TreeNode + Clone + Debug + Serialize> {
     pub root: T,
     pub store: Store<T>,
     pub height: Height,
+    /// every non padding node at a level less than or equal to `store_depth` is in the store
+    pub store_depth: u8,
 }
+
+impl<T: TreeNode + Clone + Debug + Serialize> SMT<T> {
+    /// replaces the leaf of `user_id` with the leaf for `new_record` and recomputes only the
+    /// ancestors of the leaf, the stored ancestors are updated in place and the new root is returned
+    /// the `x` cordinate of the user is kept so the `RecordMap` stays valid
+    /// with `store_depth` equal to the height only the `height` siblings of the path are read
+    pub fn update_leaf<const N_CURR: usize>(
+        &mut self,
+        user_id: &str,
+        new_record: &Record<N_CURR>,
+        record_map: &RecordMap,
+        tree_params: &TreeParams,
+    ) -> Result<T> {
+        let leaf_pos = *record_map
+            .get(user_id)
+            .ok_or(ErrorKind::UserNotFound(user_id.to_string()))?;
+        let (_, leaf) = new_leaf_node::<T, N_CURR>(tree_params, new_record, leaf_pos.x_cord());
+        self.store.map.insert(leaf_pos, leaf.clone());
+
+        let padding_fn = |pos: &NodePosition| tree_params.padding_node_content(pos);
+        let (siblings, lefts) =
+            Siblings::generate_path_single_threaded(self, leaf_pos, &padding_fn)?;
+
+        let mut current = leaf;
+        let mut current_pos = leaf_pos;
+        for (sibling, left) in siblings.0.iter().zip(lefts) {
+            current_pos = current_pos.get_parent_node_pos();
+            current = if left {
+                T::merge(sibling, &current, &current_pos)
+            } else {
+                T::merge(&current, sibling, &current_pos)
+            };
+            if let Some(stored) = self.store.map.get_mut(&current_pos) {
+                *stored = current.clone();
+            }
+        }
+        self.root = current.clone();
+        Ok(current)
+    }
+}
+
+impl<T: ServedNode> SMT<T> {
+    /// strips the openings of every node so the tree can be persisted
+    pub fn to_partial(&self) -> SMT<PartialNode> {
+        SMT {
+            root: self.root.to_partial(),
+            store: Store {
+                map: self
+                    .store
+                    .map
+                    .iter()
+                    .map(|(pos, node)| (*pos, node.to_partial()))
+                    .collect(),
+            },
+            height: self.height,
+            store_depth: self.store_depth,
+        }
+    }
+}
+
 /// The tree paramets such as `master_salt` , `salt_s` , `salt_b`
 #[derive(Clone, Debug)]
 pub struct TreeParams {
@@ -30,6 +100,63 @@ pub struct TreeParams {
     pub salt_b: Salt,
 }
 
+impl TreeParams {
+    /// the hardcoded params of the csv demo, never to be used outside of development
+    pub fn demo() -> Self {
+        TreeParams {
+            master_secret: (*b"Mina Blockchain enables privacy!").into(),
+            salt_s: (*b"message_encryption_salt_value_12").into(),
+            salt_b: (*b"Bright stars illuminate dark sky").into(),
+        }
+    }
+
+    pub fn is_demo(&self) -> bool {
+        self.fingerprint() == Self::demo().fingerprint()
+    }
+
+    /// seed for the leaf placement in the given epoch `kdf(master_secret, "placement" | epoch)`
+    pub fn placement_seed(&self, epoch: u64) -> [u8; 32] {
+        let mut id = b"placement".to_vec();
+        id.extend_from_slice(&epoch.to_le_bytes());
+        kdf::kdf(None, Some(&id), self.master_secret.as_bytes_slice())
+    }
+
+    /// a fingerprint of the params which can be stored without revealing them
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"oram-smst tree params");
+        hasher.update(self.master_secret.as_bytes_slice());
+        hasher.update(self.salt_s.as_bytes());
+        hasher.update(self.salt_b.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    /// the blinding factor and user salt of the leaf at `x_cord`
+    /// `w = kdf(master_secret, x)` , blinding factor `b = kdf(w, salt_b)` and user salt `s = kdf(w, salt_s)`
+    pub fn leaf_secrets(&self, x_cord: u64) -> (Secret, Secret) {
+        let w = kdf::kdf(
+            None,
+            Some(&x_cord.to_le_bytes()),
+            self.master_secret.as_bytes_slice(),
+        );
+        // `b` in dapol +
+        let blinding_factor = kdf::kdf(Some(&self.salt_b.as_bytes()), None, &w).into();
+        // `s` in dapol +
+        let user_salt = kdf::kdf(Some(&self.salt_s.as_bytes()), None, &w).into();
+        (blinding_factor, user_salt)
+    }
+
+    /// the padding node content for the node at `position`
+    pub fn padding_node_content(&self, position: &NodePosition) -> PaddingNodeContent {
+        new_padding_node_content(
+            self.master_secret.as_bytes_slice(),
+            &self.salt_s.as_bytes(),
+            &self.salt_b.as_bytes(),
+            position,
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct TreeBuilder<T: TreeNode + Clone + Debug + Serialize, const N_CURR: usize> {
     records: Vec<Record<N_CURR>>,
@@ -56,57 +183,45 @@ impl<T: TreeNode + Clone + Debug + Serialize, const N_CURR: usize> TreeBuilder<T
         Self::new(records, height, tree_params)
     }
 
+    pub fn height(&self) -> Height {
+        self.height
+    }
+
+    /// places the leaves using the given seed instead of the os rng
+    /// the same seed and records always produce the same tree
+    pub fn with_placement_seed(mut self, seed: [u8; 32]) -> Self {
+        self.x_cord_generator = XCordGenerator::from_seed(self.height, seed);
+        self
+    }
+
+    /// places the leaves using the seed derived from the master secret for the given `epoch`
+    /// so the auditors holding the tree params can reproduce the tree and root
+    pub fn with_epoch(self, epoch: u64) -> Self {
+        let seed = self.tree_params.placement_seed(epoch);
+        self.with_placement_seed(seed)
+    }
+
     /// build the tree single threaded and with given records
     pub fn build_single_threaded(
         &mut self,
         store_depth: Option<u8>,
     ) -> Result<(SMT<T>, RecordMap)> {
         use crate::tree_builder::single::single_threaded_tree_builder;
-        tracing::info!(
-            "ORAM-SMT Configuration
-            +----------------+------------------------+
-            | Height         | {height:16} |
-            | Entities       | {num_entities:16} |
-            | Master Secret  | <REDACTED>            |
-            | Salt B         | 0x{salt_b:14} |
-            | Salt S         | 0x{salt_s:14} |
-            +----------------+------------------------+",
-            height = self.height.as_u32(),
-            num_entities = self.records.len(),
-            salt_b = &self.tree_params.salt_b.as_hex(),
-            salt_s = &self.tree_params.salt_s.as_hex()
-        );
-        let mut leaf_nodes = Vec::with_capacity(self.records.len());
+        self.log_configuration();
+        let mut x_cords = Vec::with_capacity(self.records.len());
 
         let mut record_map = HashMap::new();
 
         for record in &self.records {
             let new_x_cord = self.x_cord_generator.gen_x_cord()?;
-            let node_pos = NodePosition::new(new_x_cord, Height::new(0));
-            let master_secret = kdf::kdf(
-                None,
-                Some(&new_x_cord.to_le_bytes()),
-                self.tree_params.master_secret.as_bytes_slice(),
-            );
-            // `b` in dapol +
-            let blinding_factor = kdf::kdf(
-                Some(&self.tree_params.salt_b.as_bytes()),
-                None,
-                &master_secret,
-            )
-            .into();
-            // `s` in dapol +
-            let user_salt = kdf::kdf(
-                Some(&self.tree_params.salt_s.as_bytes()),
-                None,
-                &master_secret,
-            )
-            .into();
-            let node = T::new_leaf(blinding_factor, record, user_salt);
-            leaf_nodes.push((node_pos, node));
+            x_cords.push(new_x_cord);
 
-            record_map.insert(record.hashed_email.clone(), node_pos);
+            record_map.insert(
+                record.hashed_email.clone(),
+                NodePosition::new(new_x_cord, Height::new(0)),
+            );
         }
+        let mut leaf_nodes = new_leaf_nodes(&self.tree_params, &self.records, &x_cords);
         leaf_nodes.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
         let padding_fn = |pos: &NodePosition| {
             new_padding_node_content(
@@ -128,6 +243,114 @@ impl<T: TreeNode + Clone + Debug + Serialize, const N_CURR: usize> TreeBuilder<T
             record_map,
         ))
     }
+
+    /// build the tree on the rayon thread pool, the leaf commitments and the subtrees
+    /// are computed in parallel and the result is the same as `build_single_threaded`
+    pub fn build_multi_threaded(&mut self, store_depth: Option<u8>) -> Result<(SMT<T>, RecordMap)>
+    where
+        T: Send + Sync,
+    {
+        use crate::tree_builder::multi::multi_threaded_tree_builder;
+        self.log_configuration();
+
+        // x cordinates are drawn sequentially from the generator
+        let x_cords = self
+            .records
+            .iter()
+            .map(|_| self.x_cord_generator.gen_x_cord())
+            .collect::<Result<Vec<u64>>>()?;
+        self.x_cord_generator.flush();
+
+        let tree_params = &self.tree_params;
+        let mut leaf_nodes: Vec<(NodePosition, T)> = self
+            .records
+            .par_chunks(LEAF_BATCH_SIZE)
+            .zip(x_cords.par_chunks(LEAF_BATCH_SIZE))
+            .flat_map_iter(|(records, x_cords)| new_leaf_nodes(tree_params, records, x_cords))
+            .collect();
+        leaf_nodes.par_sort_unstable_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+
+        let record_map = self
+            .records
+            .iter()
+            .zip(x_cords.iter())
+            .map(|(record, x_cord)| {
+                (
+                    record.hashed_email.clone(),
+                    NodePosition::new(*x_cord, Height::new(0)),
+                )
+            })
+            .collect();
+
+        let padding_fn = |pos: &NodePosition| {
+            new_padding_node_content(
+                tree_params.master_secret.as_bytes_slice(),
+                &tree_params.salt_s.as_bytes(),
+                &tree_params.salt_b.as_bytes(),
+                pos,
+            )
+        };
+
+        Ok((
+            multi_threaded_tree_builder(
+                leaf_nodes,
+                &self.height,
+                store_depth.unwrap_or_default(),
+                &padding_fn,
+            )?,
+            record_map,
+        ))
+    }
+
+    fn log_configuration(&self) {
+        tracing::info!(
+            "ORAM-SMT Configuration
+            +----------------+------------------------+
+            | Height         | {height:16} |
+            | Entities       | {num_entities:16} |
+            | Master Secret  | <REDACTED>            |
+            | Salt B         | <REDACTED>            |
+            | Salt S         | <REDACTED>            |
+            | Fingerprint    | {fingerprint:.16}       |
+            +----------------+------------------------+",
+            height = self.height.as_u32(),
+            num_entities = self.records.len(),
+            fingerprint = self.tree_params.fingerprint()
+        );
+    }
+}
+
+/// creates the leaf nodes for `records` placed at the matching `x_cords`
+/// the commitments are computed in one batch with `TreeNode::new_leaves`
+pub fn new_leaf_nodes<T: TreeNode, const N_CURR: usize>(
+    tree_params: &TreeParams,
+    records: &[Record<N_CURR>],
+    x_cords: &[u64],
+) -> Vec<(NodePosition, T)> {
+    let leaves = records
+        .iter()
+        .zip(x_cords)
+        .map(|(record, x_cord)| {
+            let (blinding_factor, user_salt) = tree_params.leaf_secrets(*x_cord);
+            (blinding_factor, record, user_salt)
+        })
+        .collect();
+    x_cords
+        .iter()
+        .map(|x_cord| NodePosition::new(*x_cord, Height::new(0)))
+        .zip(T::new_leaves(leaves))
+        .collect()
+}
+
+/// creates the leaf node for a record placed at `x_cord`
+pub fn new_leaf_node<T: TreeNode, const N_CURR: usize>(
+    tree_params: &TreeParams,
+    record: &Record<N_CURR>,
+    x_cord: u64,
+) -> (NodePosition, T) {
+    let node_pos = NodePosition::new(x_cord, Height::new(0));
+    let (blinding_factor, user_salt) = tree_params.leaf_secrets(x_cord);
+    (node_pos, T::new_leaf(blinding_factor, record, user_salt))
 }
 
 pub fn new_padding_node_content(
@@ -146,7 +369,9 @@ pub fn new_padding_node_content(
 #[derive(Debug)]
 /// An X cordinate generator
 pub struct XCordGenerator {
-    rng: SmallRng,
+    rng: ChaCha20Rng,
+    /// when set the rng is reset to this seed on every flush
+    seed: Option<[u8; 32]>,
     x_cords: HashMap<u64, u64>,
     max_x_cord: u64,
     i: u64,
@@ -154,7 +379,19 @@ pub struct XCordGenerator {
 impl XCordGenerator {
     pub fn new(height: Height) -> Self {
         Self {
-            rng: SmallRng::from_os_rng(),
+            rng: ChaCha20Rng::from_os_rng(),
+            seed: None,
+            x_cords: HashMap::new(),
+            max_x_cord: height.max_nodes(),
+            i: 0,
+        }
+    }
+
+    /// a generator producing the same sequence of cordinates for the same seed
+    pub fn from_seed(height: Height, seed: [u8; 32]) -> Self {
+        Self {
+            rng: ChaCha20Rng::from_seed(seed),
+            seed: Some(seed),
             x_cords: HashMap::new(),
             max_x_cord: height.max_nodes(),
             i: 0,
@@ -183,6 +420,9 @@ impl XCordGenerator {
     pub fn flush(&mut self) {
         self.i = 0;
         self.x_cords.clear();
+        if let Some(seed) = self.seed {
+            self.rng = ChaCha20Rng::from_seed(seed);
+        }
     }
 }
 #[cfg(test)]
@@ -191,10 +431,11 @@ mod tests {
         node_position::{Height, NodePosition},
         nodes::partial::PartialNode,
         proofs::MerkleWitness,
-        record::random_records,
+        record::{random_records, Record},
         salt::Salt,
         secret::random_secret,
         tree::{new_padding_node_content, TreeBuilder, TreeParams},
+        tree_builder::single::single_threaded_tree_builder,
     };
 
     #[test]
@@ -234,4 +475,78 @@ mod tests {
 
         merkle_witness.save(None).unwrap();
     }
+
+    #[test]
+    pub fn test_seeded_placement_is_reproducible() {
+        let rand_records = random_records::<3>(20);
+        let tree_params = TreeParams {
+            salt_b: Salt::generate_random(),
+            salt_s: Salt::generate_random(),
+            master_secret: random_secret(),
+        };
+        let build = |epoch: u64| {
+            let mut tree_builder: TreeBuilder<PartialNode, 3> =
+                TreeBuilder::from_records(rand_records.clone(), tree_params.clone())
+                    .with_epoch(epoch);
+            let first = tree_builder.build_single_threaded(None).unwrap();
+            let second = tree_builder.build_single_threaded(None).unwrap();
+            assert_eq!(first.0.root, second.0.root);
+            assert_eq!(first.1, second.1);
+            first
+        };
+        let (tree, record_map) = build(1);
+        let (same_tree, same_record_map) = build(1);
+        assert_eq!(tree.root, same_tree.root);
+        assert_eq!(record_map, same_record_map);
+
+        let (_, next_record_map) = build(2);
+        assert_ne!(record_map, next_record_map);
+    }
+
+    #[test]
+    pub fn test_update_leaf_matches_rebuild() {
+        let rand_records = random_records::<3>(6);
+        let tree_params = TreeParams {
+            salt_b: Salt::generate_random(),
+            salt_s: Salt::generate_random(),
+            master_secret: random_secret(),
+        };
+        // partially stored trees and a fully stored one
+        for store_depth in [1, 3] {
+            let mut tree_builder: TreeBuilder<PartialNode, 3> =
+                TreeBuilder::new(rand_records.clone(), Height::new(3), tree_params.clone());
+            let (mut tree, record_map) = tree_builder
+                .build_single_threaded(Some(store_depth))
+                .unwrap();
+
+            let user = rand_records[2].hashed_email.clone();
+            let new_record = Record::new(&[10, 20, 30], user.clone());
+            let new_root = tree
+                .update_leaf(&user, &new_record, &record_map, &tree_params)
+                .unwrap();
+            assert_eq!(new_root, tree.root);
+
+            let mut leaf_nodes: Vec<_> = tree
+                .store
+                .map
+                .iter()
+                .filter(|(pos, _)| pos.1 == Height::new(0))
+                .map(|(pos, node)| (*pos, node.clone()))
+                .collect();
+            leaf_nodes.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+            let padding_fn = |pos: &NodePosition| tree_params.padding_node_content(pos);
+            let rebuilt =
+                single_threaded_tree_builder(leaf_nodes, &Height::new(3), store_depth, &padding_fn)
+                    .unwrap();
+            assert_eq!(rebuilt.root, tree.root);
+            assert_eq!(rebuilt.store.map, tree.store.map);
+
+            let merkle_witness: MerkleWitness<PartialNode, 3> =
+                MerkleWitness::generate_witness(user, &tree, &record_map, &padding_fn).unwrap();
+            let root = merkle_witness
+                .path
+                .get_root_from_path(merkle_witness.user_leaf.clone(), &merkle_witness.lefts);
+            assert_eq!(root, new_root);
+        }
+    }
 }
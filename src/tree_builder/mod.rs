@@ -6,7 +6,7 @@ use crate::{
     nodes::TreeNode,
     secret::Secret,
 };
-mod multi;
+pub mod multi;
 pub mod single;
 
 #[derive(Debug)]
@@ -56,7 +56,7 @@ impl<T: TreeNode + Debug> Pair<T> {
         match (&self.left, &self.right) {
             (Some((left_pos, left)), Some((_, right))) => {
                 let parent_pos = left_pos.get_parent_node_pos();
-                let parent = T::merge(left, right);
+                let parent = T::merge(left, right, &parent_pos);
                 Ok((parent_pos, parent))
             }
             _ => Err(ErrorKind::FoundUnmatchedNodes),
@@ -72,10 +72,10 @@ pub struct PaddingNodeContent(Secret, Secret);
 
 impl PaddingNodeContent {
     pub fn bliding_factor(&self) -> Secret {
-        self.0
+        self.0.clone()
     }
     pub fn user_secret(&self) -> Secret {
-        self.1
+        self.1.clone()
     }
 
     pub fn new(blinding_factor: Secret, user_secret: Secret) -> Self {
@@ -0,0 +1,144 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    error::{ErrorKind, Result},
+    node_position::{Height, NodePosition},
+    nodes::TreeNode,
+    tree::SMT,
+};
+
+use super::{single::build_layers, PaddingNodeContent};
+
+/// builds the whole tree in parallel and returns the same tree as `single_threaded_tree_builder`
+/// the leaf range is split in `2^k` subtrees which are built on the rayon thread pool
+/// and the roots of the subtrees are merged upto the root on the calling thread
+pub fn multi_threaded_tree_builder<
+    T: TreeNode + Clone + Debug + Serialize + Send + Sync,
+    F: Fn(&NodePosition) -> PaddingNodeContent + Sync,
+>(
+    leaf_nodes: Vec<(NodePosition, T)>,
+    height: &Height,
+    store_depth: u8,
+    padding_node_content: &F,
+) -> Result<SMT<T>> {
+    let max_leafs = height.max_nodes();
+    if leaf_nodes.len() > max_leafs as usize {
+        return Err(ErrorKind::TooManyLeafNodesForHeight {
+            given: leaf_nodes.len() as u64,
+            max: max_leafs,
+        });
+    }
+
+    let subtree_height = height.as_u8() - split_levels(height);
+
+    // leaf nodes are sorted so the leaves of a subtree are contiguous
+    let mut subtrees: Vec<Vec<(NodePosition, T)>> = vec![];
+    let mut current_subtree = None;
+    for (pos, node) in leaf_nodes {
+        let subtree = pos.x_cord() >> subtree_height;
+        if current_subtree != Some(subtree) {
+            subtrees.push(vec![]);
+            current_subtree = Some(subtree);
+        }
+        subtrees.last_mut().unwrap().push((pos, node));
+    }
+
+    let built: Vec<((NodePosition, T), HashMap<NodePosition, T>)> = subtrees
+        .into_par_iter()
+        .map(|leaves| {
+            let mut node_map = HashMap::new();
+            let root = build_layers(
+                leaves,
+                0,
+                subtree_height,
+                store_depth,
+                padding_node_content,
+                &mut node_map,
+            );
+            (root, node_map)
+        })
+        .collect();
+
+    let mut node_map = HashMap::new();
+    let mut subtree_roots = Vec::with_capacity(built.len());
+    for (root, subtree_map) in built {
+        node_map.extend(subtree_map);
+        subtree_roots.push(root);
+    }
+
+    let (_, root) = build_layers(
+        subtree_roots,
+        subtree_height,
+        height.as_u8(),
+        store_depth,
+        padding_node_content,
+        &mut node_map,
+    );
+
+    Ok(SMT {
+        root,
+        store: crate::store::Store { map: node_map },
+        height: *height,
+        store_depth,
+    })
+}
+
+/// number of top levels to split on, gives a few subtrees per thread for load balancing
+fn split_levels(height: &Height) -> u8 {
+    let threads = rayon::current_num_threads().max(1);
+    let levels = threads.next_power_of_two().ilog2() as u8 + 2;
+    levels.min(height.as_u8())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        node_position::{Height, NodePosition},
+        nodes::partial::PartialNode,
+        record::random_records,
+        salt::Salt,
+        secret::random_secret,
+        tree::{new_leaf_node, new_padding_node_content, TreeParams, XCordGenerator},
+        tree_builder::single::single_threaded_tree_builder,
+    };
+
+    use super::multi_threaded_tree_builder;
+
+    #[test]
+    fn multi_threaded_matches_single_threaded() {
+        let height = Height::new(8);
+        let records = random_records::<3>(150);
+        let tree_params = TreeParams {
+            master_secret: random_secret(),
+            salt_s: Salt::generate_random(),
+            salt_b: Salt::generate_random(),
+        };
+        let mut x_cord_generator = XCordGenerator::new(height);
+        let mut leaf_nodes: Vec<(NodePosition, PartialNode)> = records
+            .iter()
+            .map(|record| {
+                let x_cord = x_cord_generator.gen_x_cord().unwrap();
+                new_leaf_node(&tree_params, record, x_cord)
+            })
+            .collect();
+        leaf_nodes.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+
+        let padding_fn = |pos: &NodePosition| {
+            new_padding_node_content(
+                tree_params.master_secret.as_bytes_slice(),
+                &tree_params.salt_s.as_bytes(),
+                &tree_params.salt_b.as_bytes(),
+                pos,
+            )
+        };
+
+        let single =
+            single_threaded_tree_builder(leaf_nodes.clone(), &height, 3, &padding_fn).unwrap();
+        let multi = multi_threaded_tree_builder(leaf_nodes, &height, 3, &padding_fn).unwrap();
+        assert_eq!(single.root, multi.root);
+        assert_eq!(single.store.map, multi.store.map);
+    }
+}
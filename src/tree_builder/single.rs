@@ -6,6 +6,7 @@ use crate::{
     error::{ErrorKind, Result},
     node_position::{Direction, Height, NodePosition},
     nodes::TreeNode,
+    store::NodeMap,
     tree::SMT,
 };
 
@@ -32,8 +33,37 @@ pub fn single_threaded_tree_builder<
         });
     }
 
-    let mut nodes = leaf_nodes;
-    for y in 0..height.as_u8() {
+    let (_, root) = build_layers(
+        leaf_nodes,
+        0,
+        height.as_u8(),
+        store_depth,
+        padding_node_content,
+        &mut node_map,
+    );
+
+    Ok(SMT {
+        root,
+        store: crate::store::Store { map: node_map },
+        height: *height,
+        store_depth,
+    })
+}
+
+/// merges the sorted `nodes` at level `from` upto level `to` and returns the node at level `to`
+/// the nodes visited at a level less than or equal to `store_depth` are inserted in `node_map`
+pub(crate) fn build_layers<
+    T: TreeNode + Clone + Debug + Serialize,
+    F: Fn(&NodePosition) -> PaddingNodeContent,
+>(
+    mut nodes: Vec<(NodePosition, T)>,
+    from: u8,
+    to: u8,
+    store_depth: u8,
+    padding_node_content: &F,
+    node_map: &mut NodeMap<T>,
+) -> (NodePosition, T) {
+    for y in from..to {
         let mut pairs = vec![];
         for (node_pos, node) in nodes.iter() {
             if y <= store_depth {
@@ -82,9 +112,5 @@ pub fn single_threaded_tree_builder<
             .collect();
     }
 
-    Ok(SMT {
-        root: nodes.pop().unwrap().1,
-        store: crate::store::Store { map: node_map },
-        height: *height,
-    })
+    nodes.pop().unwrap()
 }